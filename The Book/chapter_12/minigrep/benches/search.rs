@@ -0,0 +1,100 @@
+// Times the whole-buffer searches against the line-by-line loop they replaced.
+// Run with `cargo bench`.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use minigrep::{search, search_any, Match};
+
+// How `search` used to work: `contains` on every line, then find the submatches on the ones that hit
+fn search_line_by_line<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    let mut results = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        if line.contains(query) {
+            let submatches = line.match_indices(query).map(|(start, found)| start..start + found.len()).collect();
+            let byte_offset = line.as_ptr() as usize - contents.as_ptr() as usize;
+            results.push(Match { line_number: index + 1, byte_offset, line, submatches });
+        }
+    }
+
+    results
+}
+
+// And the obvious way to look for several queries with it
+fn search_any_line_by_line<'a>(queries: &[&str], contents: &'a str) -> Vec<Match<'a>> {
+    let mut results = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        if queries.iter().any(|query| line.contains(query)) {
+            let mut submatches: Vec<_> = queries.iter()
+                .flat_map(|query| line.match_indices(query).map(|(start, found)| start..start + found.len()))
+                .collect();
+            submatches.sort_by_key(|range| range.start);
+            let byte_offset = line.as_ptr() as usize - contents.as_ptr() as usize;
+            results.push(Match { line_number: index + 1, byte_offset, line, submatches });
+        }
+    }
+
+    results
+}
+
+// Something shaped like a service log, with the occasional line worth finding
+fn log(lines: usize) -> String {
+    let mut contents = String::new();
+    for i in 0..lines {
+        let level = match i % 1000 {
+            0 => "ERROR connection reset by peer",
+            500 => "WARN request timed out after 30s",
+            _ => "INFO handled request in 12ms",
+        };
+        contents.push_str(&format!(
+            "2024-05-01T12:{:02}:{:02}.{:03}Z worker-{} {level} path=/api/v1/items/{i}\n",
+            i / 60000 % 60, i / 1000 % 60, i % 1000, i % 16
+        ));
+    }
+    contents
+}
+
+fn time<T>(name: &str, size: usize, mut f: impl FnMut() -> T) {
+    const RUNS: u32 = 5;
+
+    // Once to warm up, then take the best of a few
+    black_box(f());
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        black_box(f());
+        best = best.min(start.elapsed());
+    }
+
+    let throughput = size as f64 / best.as_secs_f64() / (1024.0 * 1024.0);
+    println!("{name:<44} {best:>10.2?} {throughput:>8.0} MiB/s");
+}
+
+fn main() {
+    let contents = log(500_000);
+    let size = contents.len();
+    println!("searching {} MiB of log\n", size / (1024 * 1024));
+
+    for query in ["connection reset", "timed out after 30s", "worker-3 INFO"] {
+        assert_eq!(search_line_by_line(query, &contents), search(query, &contents));
+        time(&format!("line by line: {query:?}"), size, || search_line_by_line(query, &contents));
+        time(&format!("whole buffer: {query:?}"), size, || search(query, &contents));
+    }
+
+    // A few queries get a finder each and more go into one Aho-Corasick automaton, so these
+    // straddle the point where one takes over from the other
+    let words = ["connection reset", "timed out", "panicked", "deadlock", "out of memory"];
+    let queries: Vec<String> = words.iter().map(|word| word.to_string())
+        .chain((0..100).map(|i| format!("error code E{i:04}")))
+        .collect();
+    let queries: Vec<&str> = queries.iter().map(String::as_str).collect();
+
+    for count in [2, 5, 12, 13, 16, 105] {
+        let queries = &queries[..count];
+        let name = format!("{count} queries");
+        assert_eq!(search_any_line_by_line(queries, &contents).len(), search_any(queries, &contents).len());
+        time(&format!("line by line: {name}"), size, || search_any_line_by_line(queries, &contents));
+        time(&format!("whole buffer: {name}"), size, || search_any(queries, &contents));
+    }
+}
@@ -0,0 +1,239 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Settings from `~/.config/minigrep/config.toml` (or wherever MINIGREP_CONFIG points).
+// It's a small part of TOML: strings, arrays of strings, comments and a `[types]` table.
+//
+//   # Added in front of the command line's flags, so the command line still gets the last word
+//   flags = ["--sort", "-n", "-C", "2"]
+//   # Files to skip, even when they're named on the command line
+//   ignore = ["*.min.js", "target/**"]
+//
+//   # For `--type NAME`; these replace the built-in types of the same name
+//   [types]
+//   web = ["*.html", "*.css", "*.js"]
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigFile {
+    pub flags: Vec<String>,
+    pub ignore: Vec<String>,
+    pub types: Vec<(String, Vec<String>)>,
+}
+
+// Types everyone gets without a config file
+const BUILT_IN_TYPES: &[(&str, &[&str])] = &[
+    ("c", &["*.c", "*.h"]),
+    ("go", &["*.go"]),
+    ("js", &["*.js", "*.mjs", "*.cjs"]),
+    ("json", &["*.json"]),
+    ("log", &["*.log", "*.log.*"]),
+    ("md", &["*.md", "*.markdown"]),
+    ("py", &["*.py"]),
+    ("rust", &["*.rs"]),
+    ("toml", &["*.toml"]),
+];
+
+impl ConfigFile {
+    // Read the config file, if there is one. MINIGREP_CONFIG names it (or turns it off, when empty);
+    // otherwise it's minigrep/config.toml under $XDG_CONFIG_HOME or ~/.config, and it's fine for that not to exist.
+    pub fn load() -> Result<ConfigFile, String> {
+        let (path, required) = match env::var_os("MINIGREP_CONFIG") {
+            Some(path) if path.is_empty() => return Ok(ConfigFile::default()),
+            Some(path) => (PathBuf::from(path), true),
+            None => {
+                let dir = env::var_os("XDG_CONFIG_HOME")
+                    .filter(|dir| !dir.is_empty())
+                    .map(PathBuf::from)
+                    .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+                match dir {
+                    Some(dir) => (dir.join("minigrep").join("config.toml"), false),
+                    None => return Ok(ConfigFile::default()),
+                }
+            }
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(text) => ConfigFile::parse(&text).map_err(|e| format!("{}:{e}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(ConfigFile::default()),
+            Err(e) => Err(format!("{}: {e}", path.display())),
+        }
+    }
+
+    // Errors start with the line number they're about
+    pub fn parse(text: &str) -> Result<ConfigFile, String> {
+        let mut file = ConfigFile::default();
+        let mut in_types = false;
+
+        let mut lines = text.lines().enumerate();
+        while let Some((index, line)) = lines.next() {
+            let line_number = index + 1;
+            let error = |message: &str| format!("{line_number}: {message}");
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(table) = line.strip_prefix('[') {
+                match table.split('#').next().unwrap_or_default().trim_end().strip_suffix(']') {
+                    Some("types") => in_types = true,
+                    Some(_) => return Err(error("the only table is [types]")),
+                    None => return Err(error("expected ] after the table name")),
+                }
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("expected key = value"));
+            };
+            let key = key.trim().trim_matches('"');
+
+            // An array can carry on over several lines until its closing bracket
+            let mut value = value.trim().to_string();
+            while value.starts_with('[') && parse_value(&value).is_err_and(|e| e == UNCLOSED_ARRAY) {
+                match lines.next() {
+                    Some((_, more)) => {
+                        value.push('\n');
+                        value.push_str(more);
+                    }
+                    None => return Err(error(UNCLOSED_ARRAY)),
+                }
+            }
+            let values = parse_value(&value).map_err(error)?;
+
+            match (in_types, key) {
+                (true, name) => file.types.push((name.to_string(), values)),
+                (false, "flags") => file.flags = values,
+                (false, "ignore") => file.ignore = values,
+                (false, _) => return Err(error(&format!("unknown setting {key:?}"))),
+            }
+        }
+
+        Ok(file)
+    }
+
+    // The globs for `--type name`, with the file's types taking priority over the built-in ones
+    pub fn globs(&self, name: &str) -> Option<Vec<String>> {
+        if let Some((_, globs)) = self.types.iter().rev().find(|(type_name, _)| type_name == name) {
+            return Some(globs.clone());
+        }
+        BUILT_IN_TYPES.iter()
+            .find(|(type_name, _)| *type_name == name)
+            .map(|(_, globs)| globs.iter().map(|glob| glob.to_string()).collect())
+    }
+}
+
+const UNCLOSED_ARRAY: &str = "array is missing its closing ]";
+
+// A string, or an array of them, followed by nothing but maybe a comment
+fn parse_value(value: &str) -> Result<Vec<String>, &'static str> {
+    let mut chars = value.chars().peekable();
+    let skip_blanks = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while let Some(&c) = chars.peek() {
+            match c {
+                c if c.is_whitespace() => {
+                    chars.next();
+                }
+                '#' => {
+                    while chars.next_if(|&c| c != '\n').is_some() {}
+                }
+                _ => break,
+            }
+        }
+    };
+
+    let values = if chars.next_if_eq(&'[').is_some() {
+        let mut values = Vec::new();
+        loop {
+            skip_blanks(&mut chars);
+            match chars.peek() {
+                Some(']') => {
+                    chars.next();
+                    break;
+                }
+                Some(_) => values.push(parse_string(&mut chars)?),
+                None => return Err(UNCLOSED_ARRAY),
+            }
+            skip_blanks(&mut chars);
+            match chars.next() {
+                Some(',') => {}
+                Some(']') => break,
+                Some(_) => return Err("expected , or ] in array"),
+                None => return Err(UNCLOSED_ARRAY),
+            }
+        }
+        values
+    } else {
+        vec![parse_string(&mut chars)?]
+    };
+
+    skip_blanks(&mut chars);
+    match chars.next() {
+        None => Ok(values),
+        Some(_) => Err("unexpected text after the value"),
+    }
+}
+
+// "basic" strings with backslash escapes, or 'literal' strings without
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, &'static str> {
+    let quote = match chars.next() {
+        Some(quote @ ('"' | '\'')) => quote,
+        _ => return Err("expected a quoted string"),
+    };
+
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            Some(c) if c == quote => return Ok(string),
+            Some('\\') if quote == '"' => match chars.next() {
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                Some(c @ ('"' | '\\')) => string.push(c),
+                _ => return Err("unknown escape in string"),
+            },
+            Some('\n') | None => return Err("string is missing its closing quote"),
+            Some(c) => string.push(c),
+        }
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_settings() {
+        let file = ConfigFile::parse(r#"
+# defaults
+flags = ["--sort", "-C", "2"]   # trailing comment
+ignore = [
+    '*.min.js',
+    "target/**",  # built files
+]
+
+[types]
+web = ["*.html", "*.css"]
+rust = "*.rs.in"
+"#).unwrap();
+
+        assert_eq!(vec!["--sort", "-C", "2"], file.flags);
+        assert_eq!(vec!["*.min.js", "target/**"], file.ignore);
+        assert_eq!(Some(vec!["*.html".to_string(), "*.css".to_string()]), file.globs("web"));
+        assert_eq!(Some(vec!["*.rs.in".to_string()]), file.globs("rust"));
+        assert_eq!(Some(vec!["*.toml".to_string()]), file.globs("toml"));
+        assert_eq!(None, file.globs("cobol"));
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        assert_eq!(Err("2: unknown setting \"colour\"".to_string()), ConfigFile::parse("\ncolour = \"always\""));
+        assert_eq!(Err("1: string is missing its closing quote".to_string()), ConfigFile::parse("flags = [\"--sort]"));
+        assert_eq!(Err("1: array is missing its closing ]".to_string()), ConfigFile::parse("flags = [\"--sort\","));
+        assert_eq!(Err("3: the only table is [types]".to_string()), ConfigFile::parse("\n\n[aliases]"));
+    }
+}
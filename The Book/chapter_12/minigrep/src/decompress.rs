@@ -0,0 +1,191 @@
+use std::io::{self, BufRead, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+// Compressed input is piped through the usual command-line tool for its format (gzip, zstd,
+// bzip2 or xz, which have to be installed), so it streams like anything else and never touches
+// the disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Format {
+    pub fn from_extension(path: &str) -> Option<Format> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension {
+            "gz" | "tgz" => Some(Format::Gzip),
+            "zst" | "zstd" => Some(Format::Zstd),
+            "bz2" | "tbz2" => Some(Format::Bzip2),
+            "xz" | "txz" => Some(Format::Xz),
+            _ => None,
+        }
+    }
+
+    pub fn from_magic(start: &[u8]) -> Option<Format> {
+        match start {
+            [0x1f, 0x8b, ..] => Some(Format::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Format::Zstd),
+            [b'B', b'Z', b'h', ..] => Some(Format::Bzip2),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Format::Xz),
+            _ => None,
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Zstd => "zstd",
+            Format::Bzip2 => "bzip2",
+            Format::Xz => "xz",
+        }
+    }
+}
+
+// The decompressed contents of `input`, read from a child process.
+// A thread feeds `input` to the child's stdin while we read its stdout, and another collects
+// its stderr, so no pipe can fill up with the child stuck waiting on it.
+pub struct Decompressor {
+    format: Format,
+    child: Child,
+    stdout: ChildStdout,
+    feeder: Option<JoinHandle<io::Result<u64>>>,
+    errors: Option<JoinHandle<String>>,
+}
+
+impl Decompressor {
+    pub fn spawn<R: BufRead + Send + 'static>(format: Format, mut input: R) -> io::Result<Decompressor> {
+        let mut child = Command::new(format.program())
+            .arg("-dc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => io::Error::new(
+                    e.kind(),
+                    format!("reading {0}-compressed files needs the `{0}` program, and it isn't installed or isn't on the PATH", format.program())
+                ),
+                _ => e,
+            })?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+        let errors = thread::spawn(move || {
+            let mut message = Vec::new();
+            let _ = stderr.read_to_end(&mut message);
+            String::from_utf8_lossy(&message).into_owned()
+        });
+        let feeder = thread::spawn(move || {
+            let copied = io::copy(&mut input, &mut stdin)?;
+            // Closing stdin is how the child knows there's no more input
+            drop(stdin);
+            Ok(copied)
+        });
+
+        Ok(Decompressor { format, child, stdout, feeder: Some(feeder), errors: Some(errors) })
+    }
+
+    // Once the output runs out, make sure it ran out because the child finished cleanly
+    fn finish(&mut self) -> io::Result<()> {
+        let Some(feeder) = self.feeder.take() else {
+            return Ok(());
+        };
+
+        let status = self.child.wait()?;
+        // With the child gone its stderr is closed, so this won't wait for long
+        let message = self.errors.take().and_then(|errors| errors.join().ok()).unwrap_or_default();
+        if !status.success() {
+            // These tools already say who they are in their messages
+            let message = match message.trim() {
+                "" => format!("{}: {status}", self.format.program()),
+                message => message.to_string(),
+            };
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+
+        // The child is happy, so it read all its input; any error here is reading the input itself
+        match feeder.join() {
+            Ok(result) => result.map(|_| ()),
+            Err(_) => Err(io::Error::other("input thread panicked")),
+        }
+    }
+}
+
+impl Read for Decompressor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            self.finish()?;
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for Decompressor {
+    // If we stopped reading early (the reader of our output went away, say), don't leave the child behind
+    fn drop(&mut self) {
+        if self.feeder.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(Some(Format::Gzip), Format::from_extension("app.log.1.gz"));
+        assert_eq!(Some(Format::Zstd), Format::from_extension("app.log.zst"));
+        assert_eq!(None, Format::from_extension("app.log"));
+        assert_eq!(None, Format::from_extension("gz"));
+
+        assert_eq!(Some(Format::Gzip), Format::from_magic(&[0x1f, 0x8b, 0x08, 0x00]));
+        assert_eq!(Some(Format::Xz), Format::from_magic(b"\xfd7zXZ\x00\x00"));
+        assert_eq!(None, Format::from_magic(b"BZ"));
+        assert_eq!(None, Format::from_magic(b"plain text"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let compressed = Command::new("gzip")
+            .arg("-c")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .and_then(|mut gzip| {
+                gzip.stdin.take().unwrap().write_all(b"one\ntwo\n")?;
+                gzip.wait_with_output()
+            });
+        let compressed = match compressed {
+            Ok(compressed) => compressed,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                eprintln!("skipping round_trip: gzip isn't installed");
+                return;
+            }
+            Err(e) => panic!("couldn't run gzip: {e}"),
+        };
+
+        let mut decompressed = String::new();
+        Decompressor::spawn(Format::Gzip, io::Cursor::new(compressed.stdout))
+            .unwrap()
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!("one\ntwo\n", decompressed);
+
+        let mut corrupt = Decompressor::spawn(Format::Gzip, io::Cursor::new(b"\x1f\x8bnot really".to_vec())).unwrap();
+        assert!(corrupt.read_to_string(&mut String::new()).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use std::char::REPLACEMENT_CHARACTER;
+use std::io::{self, Read};
+
+// Text encodings we can read besides UTF-8, for files that came out of older Windows tools.
+// Everything is turned into UTF-8 on the way in, so matching never has to know; `Source` is how
+// positions in that UTF-8 get turned back into positions in the file.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    // ISO-8859-1: every byte is the code point with the same number
+    Latin1,
+    // Latin-1 with printable characters (€, curly quotes, ...) in place of most of 0x80-0x9F
+    Windows1252,
+}
+
+// What Windows-1252 has at 0x80-0x9F. The five bytes it leaves undefined become the C1 control
+// characters with those numbers, like Latin-1, so every byte still means exactly one character.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+impl Encoding {
+    // For `--encoding`, which takes the usual spellings
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" => Some(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    // The encoding a byte order mark at the start of `start` says the text is in, and how long
+    // the mark is
+    pub fn sniff(start: &[u8]) -> Option<(Encoding, usize)> {
+        match start {
+            [0xef, 0xbb, 0xbf, ..] => Some((Encoding::Utf8, 3)),
+            [0xff, 0xfe, ..] => Some((Encoding::Utf16Le, 2)),
+            [0xfe, 0xff, ..] => Some((Encoding::Utf16Be, 2)),
+            _ => None,
+        }
+    }
+
+    // How many bytes of the file `c` was decoded from
+    fn width(self, c: char) -> usize {
+        match self {
+            Encoding::Utf8 => c.len_utf8(),
+            Encoding::Utf16Le | Encoding::Utf16Be => c.len_utf16() * 2,
+            Encoding::Latin1 | Encoding::Windows1252 => 1,
+        }
+    }
+}
+
+// Where some decoded text came from: its encoding, and how many bytes (a byte order mark)
+// were skipped before it
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Source {
+    pub encoding: Encoding,
+    pub start: usize,
+}
+
+impl Source {
+    // How many bytes of the file `contents` took up, given `raw`, the bytes it was made from.
+    // For UTF-8 that's just `raw`: `contents` may have had bad bytes replaced, and U+FFFD is
+    // longer than the byte it stands for.
+    pub fn original_len(&self, raw: &[u8], contents: &str) -> usize {
+        match self.encoding {
+            Encoding::Utf8 => raw.len(),
+            encoding => contents.chars().map(|c| encoding.width(c)).sum(),
+        }
+    }
+
+    // Where each line of `contents` starts in the file, given that it starts at `base` and was
+    // made from `raw`. None when positions in `contents` are already positions in the file, which
+    // is UTF-8 with nothing replaced.
+    pub fn line_starts(&self, raw: &[u8], contents: &str, base: usize) -> Option<Vec<usize>> {
+        let mut starts = vec![base];
+        if self.encoding == Encoding::Utf8 {
+            if contents.as_bytes() == raw {
+                return None;
+            }
+            let ends = raw.iter().enumerate().filter(|&(_, &b)| b == b'\n').map(|(i, _)| base + i + 1);
+            starts.extend(ends);
+            return Some(starts);
+        }
+
+        let mut offset = base;
+        for c in contents.chars() {
+            offset += self.encoding.width(c);
+            if c == '\n' {
+                starts.push(offset);
+            }
+        }
+        Some(starts)
+    }
+}
+
+// Reads `inner` as `encoding`, giving back UTF-8. Bytes that don't decode become U+FFFD.
+pub struct Decoder<R> {
+    inner: R,
+    encoding: Encoding,
+    // Read but not decoded yet: half a UTF-16 code unit, or the first half of a surrogate pair
+    raw: Vec<u8>,
+    decoded: Vec<u8>,
+    // How much of `decoded` has been handed out
+    next: usize,
+    done: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(inner: R, encoding: Encoding) -> Decoder<R> {
+        Decoder { inner, encoding, raw: Vec::new(), decoded: Vec::new(), next: 0, done: false }
+    }
+
+    // Decode as much of `raw` as we can. At the end of the input, that's all of it.
+    fn decode(&mut self, at_end: bool) {
+        let mut text = String::with_capacity(self.raw.len());
+        let mut used = self.raw.len();
+
+        match self.encoding {
+            Encoding::Utf8 => {
+                // A character split between reads waits for the rest of it
+                if !at_end
+                    && let Err(e) = std::str::from_utf8(&self.raw)
+                    && e.error_len().is_none()
+                {
+                    used = e.valid_up_to();
+                }
+                text.push_str(&String::from_utf8_lossy(&self.raw[..used]));
+            }
+            Encoding::Latin1 => text.extend(self.raw.iter().map(|&b| char::from(b))),
+            Encoding::Windows1252 => text.extend(self.raw.iter().map(|&b| match b {
+                0x80..=0x9f => WINDOWS_1252_HIGH[usize::from(b - 0x80)],
+                _ => char::from(b),
+            })),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let unit = |pair: &[u8]| match self.encoding {
+                    Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                };
+                let mut units: Vec<u16> = self.raw.chunks_exact(2).map(unit).collect();
+                used = units.len() * 2;
+                // The other half of a surrogate pair might be in the next read
+                if !at_end && units.last().is_some_and(|unit| (0xd800..0xdc00).contains(unit)) {
+                    units.pop();
+                    used -= 2;
+                }
+                text.extend(char::decode_utf16(units).map(|c| c.unwrap_or(REPLACEMENT_CHARACTER)));
+                // An odd byte out at the very end
+                if at_end && used < self.raw.len() {
+                    text.push(REPLACEMENT_CHARACTER);
+                    used = self.raw.len();
+                }
+            }
+        }
+
+        self.raw.drain(..used);
+        self.decoded = text.into_bytes();
+        self.next = 0;
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.next == self.decoded.len() {
+            if self.done {
+                return Ok(0);
+            }
+            let mut chunk = [0; 8192];
+            let read = self.inner.read(&mut chunk)?;
+            self.raw.extend_from_slice(&chunk[..read]);
+            self.done = read == 0;
+            self.decode(self.done);
+        }
+
+        let len = buf.len().min(self.decoded.len() - self.next);
+        buf[..len].copy_from_slice(&self.decoded[self.next..self.next + len]);
+        self.next += len;
+        Ok(len)
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hands out one byte per read, so every split a read could make gets tried
+    struct OneByte<'a>(&'a [u8]);
+
+    impl Read for OneByte<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((&b, rest)) if !buf.is_empty() => {
+                    buf[0] = b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8], encoding: Encoding) -> String {
+        let mut text = String::new();
+        Decoder::new(OneByte(bytes), encoding).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn decodes() {
+        assert_eq!("h\u{e9}\u{1F600}\n", decode(b"h\x00\xe9\x00\x3d\xd8\x00\xde\n\x00", Encoding::Utf16Le));
+        assert_eq!("h\u{e9}\n", decode(b"\x00h\x00\xe9\x00\n", Encoding::Utf16Be));
+        // A lone surrogate, then half a code unit
+        assert_eq!("\u{FFFD}a\u{FFFD}", decode(b"\x00\xdca\x00x", Encoding::Utf16Le));
+        assert_eq!("caf\u{e9} \u{80}", decode(b"caf\xe9 \x80", Encoding::Latin1));
+        assert_eq!("caf\u{e9} \u{20AC} \u{201C}\u{8D}", decode(b"caf\xe9 \x80 \x93\x8d", Encoding::Windows1252));
+    }
+
+    #[test]
+    fn sniffs_byte_order_marks() {
+        assert_eq!(Some((Encoding::Utf16Le, 2)), Encoding::sniff(b"\xff\xfeh\x00"));
+        assert_eq!(Some((Encoding::Utf8, 3)), Encoding::sniff(b"\xef\xbb\xbfhi"));
+        assert_eq!(None, Encoding::sniff(b"hi"));
+        assert_eq!(Some(Encoding::Windows1252), Encoding::from_name("CP1252"));
+        assert_eq!(None, Encoding::from_name("ebcdic"));
+    }
+
+    #[test]
+    fn positions_in_the_original() {
+        let source = Source { encoding: Encoding::Utf16Le, start: 2 };
+        // For decoded text, `raw` is what came out of the decoder
+        assert_eq!(Some(vec![2, 8, 14]), source.line_starts(b"", "ab\n\u{1F600}\n", 2));
+        assert_eq!(6, source.original_len(b"", "ab\n"));
+        assert_eq!(4, Source { encoding: Encoding::Latin1, start: 0 }.original_len(b"", "\u{e9}t\u{e9}!"));
+
+        let utf8 = Source::default();
+        assert_eq!(None, utf8.line_starts(b"ab\n", "ab\n", 0));
+        // A bad byte is one byte of the file, not the three of the U+FFFD that replaced it
+        let raw = b"caf\xe9\nmatch\n";
+        let contents = String::from_utf8_lossy(raw);
+        assert_eq!(Some(vec![0, 5, 11]), utf8.line_starts(raw, &contents, 0));
+        assert_eq!(11, utf8.original_len(raw, &contents));
+    }
+}
@@ -0,0 +1,268 @@
+use std::error::Error;
+use std::fmt;
+
+// A boolean query (`--expr`), like `error AND NOT (timeout OR retrying)`.
+// Operators are upper case; NOT binds tightest, then AND, then OR, and two terms side by side
+// mean AND. Anything else is a term to look for, and "double quotes" make one out of spaces,
+// parentheses or an operator name.
+pub struct Query {
+    pub expr: Expr,
+    // Every distinct term, in the order they first appear; `Expr::Term` indexes into this
+    pub terms: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    Term(usize),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    // `present[i]` says whether terms[i] is somewhere on the line
+    pub fn eval(&self, present: &[bool]) -> bool {
+        match self {
+            Expr::Term(i) => present[*i],
+            Expr::Not(expr) => !expr.eval(present),
+            Expr::And(left, right) => left.eval(present) && right.eval(present),
+            Expr::Or(left, right) => left.eval(present) || right.eval(present),
+        }
+    }
+
+    // Marks the terms a matching line is matching because they're there, rather than because
+    // they aren't, so only those get highlighted
+    pub fn positive_terms(&self, positive: &mut [bool]) {
+        self.mark_terms(positive, true);
+    }
+
+    fn mark_terms(&self, positive: &mut [bool], polarity: bool) {
+        match self {
+            Expr::Term(i) => positive[*i] |= polarity,
+            Expr::Not(expr) => expr.mark_terms(positive, !polarity),
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                left.mark_terms(positive, polarity);
+                right.mark_terms(positive, polarity);
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    // 1-based, counting characters
+    pub column: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Term(String),
+}
+
+// Each token along with the column it starts at
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().enumerate().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        let column = index + 1;
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '"' => {
+                let mut term = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => term.push(c),
+                            None => return Err(ParseError { column, message: "unterminated quote" }),
+                        },
+                        Some((_, c)) => term.push(c),
+                        None => return Err(ParseError { column, message: "unterminated quote" }),
+                    }
+                }
+                if term.is_empty() {
+                    return Err(ParseError { column, message: "empty term" });
+                }
+                Token::Term(term)
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.next_if(|&(_, c)| !c.is_whitespace() && !"()\"".contains(c)) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Term(word),
+                }
+            }
+        };
+        tokens.push((column, token));
+    }
+
+    Ok(tokens)
+}
+
+pub fn parse(source: &str) -> Result<Query, ParseError> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens, next: 0, end: source.chars().count() + 1, terms: Vec::new() };
+
+    let expr = parser.or()?;
+    match parser.tokens.get(parser.next) {
+        None => Ok(Query { expr, terms: parser.terms }),
+        Some((column, Token::Close)) => Err(ParseError { column: *column, message: "unmatched )" }),
+        Some((column, _)) => Err(ParseError { column: *column, message: "expected an operator" }),
+    }
+}
+
+// Recursive descent, one method per precedence level
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    // Column just past the end, for errors about running out of input
+    end: usize,
+    terms: Vec<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(column, _)| *column)
+    }
+
+    // or := and ("OR" and)*
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    // and := not (["AND"] not)*
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.next += 1,
+                // Another term straight after this one
+                Some(Token::Term(_) | Token::Not | Token::Open) => {}
+                _ => return Ok(expr),
+            }
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+    }
+
+    // not := "NOT" not | primary
+    fn not(&mut self) -> Result<Expr, ParseError> {
+        if self.peek() == Some(&Token::Not) {
+            self.next += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    // primary := term | "(" or ")"
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let column = self.column();
+        let Some((_, token)) = self.tokens.get_mut(self.next) else {
+            return Err(ParseError { column, message: "expected a term" });
+        };
+
+        match token {
+            Token::Term(term) => {
+                let term = std::mem::take(term);
+                self.next += 1;
+                let index = match self.terms.iter().position(|known| *known == term) {
+                    Some(index) => index,
+                    None => {
+                        self.terms.push(term);
+                        self.terms.len() - 1
+                    }
+                };
+                Ok(Expr::Term(index))
+            }
+            Token::Open => {
+                self.next += 1;
+                let expr = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(ParseError { column: self.column(), message: "expected )" });
+                }
+                self.next += 1;
+                Ok(expr)
+            }
+            _ => Err(ParseError { column, message: "expected a term" }),
+        }
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(i: usize) -> Box<Expr> {
+        Box::new(Expr::Term(i))
+    }
+
+    #[test]
+    fn precedence() {
+        let query = parse("a OR b c AND NOT d").unwrap();
+        assert_eq!(vec!["a", "b", "c", "d"], query.terms);
+        assert_eq!(
+            Expr::Or(term(0), Box::new(Expr::And(Box::new(Expr::And(term(1), term(2))), Box::new(Expr::Not(term(3)))))),
+            query.expr
+        );
+
+        let query = parse(r#"(foo OR "bar baz") AND foo"#).unwrap();
+        assert_eq!(vec!["foo", "bar baz"], query.terms);
+        assert_eq!(Expr::And(Box::new(Expr::Or(term(0), term(1))), term(0)), query.expr);
+    }
+
+    #[test]
+    fn evaluates() {
+        let query = parse("error AND NOT timeout").unwrap();
+        assert!(query.expr.eval(&[true, false]));
+        assert!(!query.expr.eval(&[true, true]));
+        assert!(!query.expr.eval(&[false, false]));
+
+        let mut positive = vec![false; 2];
+        query.expr.positive_terms(&mut positive);
+        assert_eq!(vec![true, false], positive);
+    }
+
+    #[test]
+    fn errors_say_where() {
+        let error = |source| parse(source).err().unwrap();
+        assert_eq!(ParseError { column: 10, message: "expected a term" }, error("error AND"));
+        assert_eq!(ParseError { column: 12, message: "expected )" }, error("(foo OR bar"));
+        assert_eq!(ParseError { column: 4, message: "unmatched )" }, error("foo) bar"));
+        assert_eq!(ParseError { column: 5, message: "unterminated quote" }, error("foo \"bar"));
+        assert_eq!(ParseError { column: 1, message: "expected a term" }, error("OR foo"));
+    }
+}
@@ -0,0 +1,159 @@
+use std::char::ToLowercase;
+use std::ops::Range;
+use std::str::Chars;
+
+// Case-insensitive matching using Unicode case folding, rather than lowercasing whole lines.
+//
+// Folding is mostly the same as `char::to_lowercase`, except for the handful of characters below,
+// where lowercasing leaves two spellings of the same word unequal (`ß` vs `SS`, `ς` vs `Σ`).
+// We use the default (non-Turkic) folding, so `I` matches `i` and `ı` only matches itself.
+const SPECIAL_FOLDS: &[(char, &str)] = &[
+    ('\u{00B5}', "\u{03BC}"),   // µ micro sign -> μ
+    ('\u{00DF}', "ss"),         // ß
+    ('\u{0149}', "\u{02BC}n"),  // ŉ
+    ('\u{017F}', "s"),          // ſ long s
+    ('\u{0345}', "\u{03B9}"),   // combining ypogegrammeni -> ι
+    ('\u{03C2}', "\u{03C3}"),   // ς final sigma -> σ
+    ('\u{03D0}', "\u{03B2}"),   // ϐ -> β
+    ('\u{03D1}', "\u{03B8}"),   // ϑ -> θ
+    ('\u{03D5}', "\u{03C6}"),   // ϕ -> φ
+    ('\u{03D6}', "\u{03C0}"),   // ϖ -> π
+    ('\u{03F0}', "\u{03BA}"),   // ϰ -> κ
+    ('\u{03F1}', "\u{03C1}"),   // ϱ -> ρ
+    ('\u{03F5}', "\u{03B5}"),   // ϵ -> ε
+    ('\u{1E9B}', "\u{1E61}"),   // ẛ -> ṡ
+    ('\u{1E9E}', "ss"),         // ẞ capital sharp s
+    ('\u{1FBE}', "\u{03B9}"),   // prosgegrammeni -> ι
+    ('\u{FB00}', "ff"),
+    ('\u{FB01}', "fi"),
+    ('\u{FB02}', "fl"),
+    ('\u{FB03}', "ffi"),
+    ('\u{FB04}', "ffl"),
+    ('\u{FB05}', "st"),
+    ('\u{FB06}', "st"),
+];
+
+// The folded form of one character, which may be several characters long.
+// It's an iterator so folding never needs to allocate.
+enum Fold {
+    Special(Chars<'static>),
+    Lower(ToLowercase),
+}
+
+impl Iterator for Fold {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match self {
+            Fold::Special(chars) => chars.next(),
+            Fold::Lower(chars) => chars.next(),
+        }
+    }
+}
+
+fn fold(c: char) -> Fold {
+    // Everything in the table is outside ASCII, so skip the search for the common case
+    if !c.is_ascii()
+        && let Ok(index) = SPECIAL_FOLDS.binary_search_by_key(&c, |&(from, _)| from)
+    {
+        return Fold::Special(SPECIAL_FOLDS[index].1.chars());
+    }
+    Fold::Lower(c.to_lowercase())
+}
+
+// All of `text` folded, for when we need the folded text itself rather than a comparison
+pub fn fold_str(text: &str) -> String {
+    text.chars().flat_map(fold).collect()
+}
+
+// A query folded once up front, ready to be compared against the lines we search
+pub struct FoldedQuery {
+    chars: Vec<char>,
+}
+
+impl FoldedQuery {
+    pub fn new(query: &str) -> FoldedQuery {
+        FoldedQuery { chars: query.chars().flat_map(fold).collect() }
+    }
+
+    // Byte ranges in `line` that match the query, found without copying `line`.
+    // Matches always start and end on whole characters of `line`:
+    // `ss` matches `ß`, but `s` alone doesn't.
+    pub fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        let mut found = Vec::new();
+        if self.chars.is_empty() {
+            return found;
+        }
+
+        let mut start = 0;
+        while start < line.len() {
+            match self.match_at(line, start) {
+                Some(end) => {
+                    found.push(start..end);
+                    start = end;
+                }
+                None => start += line[start..].chars().next().map_or(1, char::len_utf8),
+            }
+        }
+
+        found
+    }
+
+    // If the query matches `line` starting at byte `start`, where it ends
+    fn match_at(&self, line: &str, start: usize) -> Option<usize> {
+        let mut matched = 0;
+
+        for (offset, c) in line[start..].char_indices() {
+            for folded in fold(c) {
+                if self.chars.get(matched) != Some(&folded) {
+                    return None;
+                }
+                matched += 1;
+            }
+
+            if matched == self.chars.len() {
+                return Some(start + offset + c.len_utf8());
+            }
+        }
+
+        None
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn special_folds_are_sorted() {
+        assert!(SPECIAL_FOLDS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn sharp_s() {
+        let line = "Die Straße ist lang";
+        assert_eq!(vec![4..11], FoldedQuery::new("STRASSE").find_all(line));
+        assert_eq!(vec![4..11], FoldedQuery::new("straße").find_all(line));
+        assert_eq!(vec![0..2], FoldedQuery::new("ss").find_all("ß"));
+        assert!(FoldedQuery::new("s").find_all("ß").is_empty());
+    }
+
+    #[test]
+    fn offsets_are_into_the_original_line() {
+        // İ lowercases to two characters, which used to throw every later offset off
+        let line = "İstanbul'da İSTANBUL";
+        let found = FoldedQuery::new("stanbul").find_all(line);
+        assert_eq!(vec!["stanbul", "STANBUL"], found.iter().map(|range| &line[range.clone()]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn greek_sigma_and_ligatures() {
+        assert_eq!(vec![3..11], FoldedQuery::new("ΟΔΟΣ").find_all("η οδος"));
+        assert_eq!(vec![0..5], FoldedQuery::new("file").find_all("\u{FB01}le"));
+        assert!(FoldedQuery::new("ı").find_all("I").is_empty());
+    }
+}
@@ -0,0 +1,428 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::encoding::Source;
+use crate::index;
+use crate::input::{self, Block};
+use crate::literal;
+use crate::matcher::Matcher;
+use crate::printer::Printer;
+use crate::Config;
+
+// There's no portable way to be told when a file changes without a crate, so both modes poll
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// `--follow`: print matching lines as they're added to the files, like `tail -F | grep`.
+// Only lines added from now on are searched. A file that's truncated is read again from the top,
+// and when a log is rotated (the path now names a different file) we finish the old file and
+// carry on from the start of the new one. Files that don't exist yet are waited for.
+pub fn follow(config: &Config, matcher: &Matcher) -> io::Result<()> {
+    let show_path = config.file_paths.len() > 1;
+    let mut followed: Vec<Followed<io::Stdout>> = config.file_paths.iter()
+        .map(|path| Followed::new(config, path, show_path, io::stdout))
+        .collect();
+    for file in &mut followed {
+        file.open(true);
+    }
+
+    loop {
+        let mut read_any = false;
+        for file in &mut followed {
+            match file.poll(config, matcher) {
+                Ok(read) => read_any |= read,
+                // Nobody's reading our output any more
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                Err(e) => {
+                    eprintln!("minigrep: {}: {e}", file.path);
+                    file.close();
+                }
+            }
+        }
+        if !read_any {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+// One file being followed, printing what matches to `W`
+struct Followed<W: Write> {
+    path: String,
+    show_path: bool,
+    // None while the file doesn't exist (yet, or again)
+    file: Option<File>,
+    // How far into the file we've read
+    position: u64,
+    // The end of what we've read, when it isn't a whole line yet
+    partial: Vec<u8>,
+    lines_read: usize,
+    // Only then do the lines already in the file need counting when we start at its end
+    line_numbers: bool,
+    // Lasts as long as the file does, so context carries on from one read to the next
+    printer: Printer<W>,
+    // Where a new printer writes to, when the file starts over
+    out: fn() -> W,
+}
+
+impl<W: Write> Followed<W> {
+    fn new(config: &Config, path: &str, show_path: bool, out: fn() -> W) -> Followed<W> {
+        Followed {
+            path: path.to_string(),
+            show_path,
+            file: None,
+            position: 0,
+            partial: Vec::new(),
+            lines_read: 0,
+            line_numbers: config.line_numbers,
+            printer: new_printer(config, path, show_path, out()),
+            out,
+        }
+    }
+
+    // Open the file, at its end if we're just starting out and at the start if it's new.
+    // Any problem just leaves it closed, to be tried again next time.
+    fn open(&mut self, at_end: bool) {
+        let Ok(mut file) = File::open(&self.path) else {
+            return;
+        };
+
+        // Skipping what's already there still means counting its lines, so line numbers are right.
+        // Without them we can go straight to the end.
+        let mut position = 0;
+        let mut lines = 0;
+        if at_end && !self.line_numbers {
+            match file.seek(SeekFrom::End(0)) {
+                Ok(end) => position = end,
+                Err(_) => return,
+            }
+        } else if at_end {
+            let mut buf = vec![0; input::BLOCK_SIZE];
+            loop {
+                match file.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        position += read as u64;
+                        lines += literal::count(b'\n', &buf[..read]);
+                    }
+                    Err(_) => return,
+                }
+            }
+        }
+
+        self.position = position;
+        self.lines_read = lines;
+        self.file = Some(file);
+    }
+
+    fn close(&mut self) {
+        self.file = None;
+        self.partial.clear();
+    }
+
+    // Read whatever's been added and print the lines that match. Says whether there was anything.
+    fn poll(&mut self, config: &Config, matcher: &Matcher) -> io::Result<bool> {
+        if self.file.is_none() {
+            self.restart(config);
+            self.open(false);
+            if self.file.is_none() {
+                return Ok(false);
+            }
+        }
+        let Some(file) = &mut self.file else {
+            return Ok(false);
+        };
+
+        // Shorter than what we've read: someone truncated it, so start again from the top
+        if file.metadata()?.len() < self.position {
+            eprintln!("minigrep: {}: file truncated", self.path);
+            file.seek(SeekFrom::Start(0))?;
+            self.position = 0;
+            self.partial.clear();
+            self.restart(config);
+        }
+
+        let file = self.file.as_mut().unwrap();
+        let mut added = Vec::new();
+        file.read_to_end(&mut added)?;
+        self.position += added.len() as u64;
+
+        // Only whole lines get searched; a partial one waits for the rest of it
+        self.partial.extend(added);
+        let read_any = !self.partial.is_empty();
+        if let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') {
+            let lines: Vec<u8> = self.partial.drain(..=end).collect();
+            self.print(matcher, &lines)?;
+        }
+
+        // Once we've read everything in the old file, move over to whatever the path names now
+        let ours = identity(&self.file.as_ref().unwrap().metadata()?);
+        let current = fs::metadata(&self.path).map(|metadata| identity(&metadata));
+        if current.as_ref().map_or(true, |id| *id != ours) {
+            if !self.partial.is_empty() {
+                let last = std::mem::take(&mut self.partial);
+                self.print(matcher, &last)?;
+            }
+            match current {
+                Ok(_) => eprintln!("minigrep: {}: file replaced; following the new file", self.path),
+                Err(_) => eprintln!("minigrep: {}: file went away; waiting for it to come back", self.path),
+            }
+            self.close();
+        }
+
+        Ok(read_any)
+    }
+
+    // Line numbers and context start over, the same as searching a new file would
+    fn restart(&mut self, config: &Config) {
+        self.lines_read = 0;
+        self.printer = new_printer(config, &self.path, self.show_path, (self.out)());
+    }
+
+    fn print(&mut self, matcher: &Matcher, lines: &[u8]) -> io::Result<()> {
+        let contents = String::from_utf8_lossy(lines);
+        let byte_offset = self.position as usize - self.partial.len() - lines.len();
+        // Bad bytes grow when they're replaced, so offsets come from `lines`, not `contents`
+        let line_starts = Source::default().line_starts(lines, &contents, byte_offset);
+        let block = Block {
+            contents: &contents,
+            line_offset: self.lines_read,
+            byte_offset,
+            line_starts: line_starts.as_deref(),
+        };
+        let results = block.search(|contents| matcher.search(contents));
+        self.printer.print(&block, &results)?;
+        self.lines_read += crate::lines(&contents).count();
+        Ok(())
+    }
+}
+
+fn new_printer<W: Write>(config: &Config, path: &str, show_path: bool, out: W) -> Printer<W> {
+    let printer = Printer::new(out, config);
+    if show_path { printer.with_path(path) } else { printer }
+}
+
+// What tells one file from another, to notice when the path has moved on to a new one: the
+// device and inode where there are such things
+#[cfg(unix)]
+fn identity(metadata: &fs::Metadata) -> (u64, u64) {
+    (metadata.dev(), metadata.ino())
+}
+
+// Elsewhere, the size and modification time. The file we have open and the one the path names
+// are looked at together, so for the same file they agree unless it's written to in between, and
+// then all that happens is we read it again from the top.
+#[cfg(not(unix))]
+fn identity(metadata: &fs::Metadata) -> ((u64, u32), u64) {
+    (index::modified(metadata), metadata.len())
+}
+
+// `--watch`: search, then wait for any of the files to change (or, for a directory, for files
+// under it to change, appear or go away), and search again, for as long as we're left running
+pub fn watch(mut config: Config) -> Result<(), Box<dyn Error>> {
+    let roots = config.file_paths.clone();
+    let clear = io::stdout().is_terminal();
+
+    loop {
+        let files = watched_files(&config, &roots);
+        config.file_paths = files;
+        let seen = snapshot(&config.file_paths);
+
+        if clear {
+            print!("\x1b[H\x1b[2J");
+        }
+        if config.file_paths.is_empty() {
+            eprintln!("minigrep: no files to search yet");
+        } else if let Err(e) = crate::matcher_for(&config, &config.queries)
+            .and_then(|matcher| crate::search_all(&config, &matcher))
+        {
+            // A file that couldn't be read this time might be fine the next
+            eprintln!("minigrep: {e}");
+        }
+
+        while snapshot(&watched_files(&config, &roots)) == seen {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+// What `roots` stands for right now: the files themselves, and everything under the directories
+fn watched_files(config: &Config, roots: &[String]) -> Vec<String> {
+    let mut files = Vec::new();
+    for root in roots {
+        if Path::new(root).is_dir() {
+            let under = index::files_under(root).unwrap_or_default();
+            files.extend(under.iter().map(|path| index::join(root, path)));
+        } else {
+            files.push(root.clone());
+        }
+    }
+    files.retain(|path| crate::wanted(&config.type_globs, &config.ignore, path));
+    files
+}
+
+// A file's modification time and size, or None if it's not there
+type FileState = Option<((u64, u32), u64)>;
+
+// Enough about each file to notice when it changes
+fn snapshot(files: &[String]) -> Vec<(String, FileState)> {
+    files.iter()
+        .map(|path| (path.clone(), fs::metadata(path).ok().map(|metadata| (index::modified(&metadata), metadata.len()))))
+        .collect()
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::env;
+    use std::process;
+
+    thread_local! {
+        static OUTPUT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    }
+
+    // Where the followers in these tests print to: a buffer for each test's own thread
+    struct Captured;
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            OUTPUT.with(|output| output.borrow_mut().extend_from_slice(buf));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Everything printed since last time
+    fn printed() -> String {
+        String::from_utf8(OUTPUT.with(|output| output.take())).unwrap()
+    }
+
+    // Follow `name` in a fresh directory for lines with "match" in them, numbered unless `-n` is turned off
+    fn follower(name: &str, line_numbers: &str) -> (std::path::PathBuf, Config, Matcher, Followed<Captured>) {
+        let dir = env::temp_dir().join(format!("minigrep-follow-{name}-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log").to_string_lossy().into_owned();
+        let args: Vec<String> = ["minigrep", "--no-config", "--color=never", line_numbers, "--follow", "match", &path]
+            .iter().map(|arg| arg.to_string()).collect();
+        let config = Config::build(&args).unwrap();
+        let matcher = Matcher::new(&config.queries, false);
+        let followed = Followed::new(&config, &path, false, || Captured);
+        (dir, config, matcher, followed)
+    }
+
+    fn append(path: &Path, bytes: impl AsRef<[u8]>) {
+        fs::OpenOptions::new().append(true).create(true).open(path).unwrap().write_all(bytes.as_ref()).unwrap();
+    }
+
+    #[test]
+    fn follows_appended_lines() {
+        let (dir, config, matcher, mut followed) = follower("append", "-n");
+        let log = dir.join("app.log");
+        fs::write(&log, "an old match\n").unwrap();
+
+        // What was there before we started isn't printed, but still counts for line numbers
+        followed.open(true);
+        assert!(!followed.poll(&config, &matcher).unwrap());
+        append(&log, "a new match\nnothing\nhalf a ");
+        assert!(followed.poll(&config, &matcher).unwrap());
+        assert_eq!("2:a new match\n", printed());
+
+        // A line only gets searched once it's finished
+        append(&log, b"match\n\xff match\n");
+        followed.poll(&config, &matcher).unwrap();
+        assert_eq!("4:half a match\n5:\u{fffd} match\n", printed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_to_the_end_without_line_numbers() {
+        let (dir, config, matcher, mut followed) = follower("unnumbered", "--no-line-number");
+        let log = dir.join("app.log");
+        fs::write(&log, "an old match\n").unwrap();
+
+        followed.open(true);
+        assert_eq!(13, followed.position);
+        append(&log, "a new match\n");
+        followed.poll(&config, &matcher).unwrap();
+        assert_eq!("a new match\n", printed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn starts_over_when_truncated() {
+        let (dir, config, matcher, mut followed) = follower("truncate", "-n");
+        let log = dir.join("app.log");
+        fs::write(&log, "one\ntwo\nthree match\n").unwrap();
+        followed.open(true);
+
+        fs::write(&log, "match\n").unwrap();
+        followed.poll(&config, &matcher).unwrap();
+        assert_eq!("1:match\n", printed());
+        append(&log, "match again\n");
+        followed.poll(&config, &matcher).unwrap();
+        assert_eq!("2:match again\n", printed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn moves_on_when_rotated() {
+        let (dir, config, matcher, mut followed) = follower("rotate", "-n");
+        let log = dir.join("app.log");
+        let rotated = dir.join("app.log.1");
+        fs::write(&log, "first match\n").unwrap();
+        followed.open(true);
+
+        // Whatever the old file gets before we notice is still read, then we go on to the new one
+        fs::rename(&log, &rotated).unwrap();
+        append(&rotated, "last match\n");
+        fs::write(&log, "new match\n").unwrap();
+        followed.poll(&config, &matcher).unwrap();
+        assert_eq!("2:last match\n", printed());
+        followed.poll(&config, &matcher).unwrap();
+        assert_eq!("1:new match\n", printed());
+
+        // The new file going away leaves us waiting for it to come back
+        fs::remove_file(&log).unwrap();
+        followed.poll(&config, &matcher).unwrap();
+        assert!(!followed.poll(&config, &matcher).unwrap());
+        append(&log, "back match\n");
+        followed.poll(&config, &matcher).unwrap();
+        assert_eq!("1:back match\n", printed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn watch_finds_files_under_directories() {
+        let dir = env::temp_dir().join(format!("minigrep-watch-{}", process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "one").unwrap();
+        fs::write(dir.join("notes.md"), "two").unwrap();
+        let root = dir.to_string_lossy().into_owned();
+
+        let args: Vec<String> = ["minigrep", "--no-config", "--watch", "-t", "rust", "one", &root, "missing.rs"]
+            .iter().map(|arg| arg.to_string()).collect();
+        let config = Config::build(&args).unwrap();
+        let roots = config.file_paths.clone();
+        let found = watched_files(&config, &roots);
+        assert_eq!(vec![format!("{root}/src/lib.rs"), "missing.rs".to_string()], found);
+
+        // Adding, changing and deleting files all look different
+        let before = snapshot(&found);
+        fs::write(dir.join("src/lib.rs"), "one more").unwrap();
+        assert_ne!(before, snapshot(&found));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(snapshot(&found).iter().all(|(_, state)| state.is_none()));
+    }
+}
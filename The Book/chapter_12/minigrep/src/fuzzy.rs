@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+// Approximate matching (`--max-distance N`): text within N edits of the query counts as a match,
+// where an edit is inserting, deleting or substituting one character.
+// Lines are filtered with bitap (Wu and Manber's shift-and with errors), which keeps one word of
+// state per allowed edit, and only lines that pass get the slower DP that finds where the matches are.
+pub struct Approximate {
+    pattern: Vec<char>,
+    max_distance: usize,
+    ignore_case: bool,
+    // Bit i is set in a character's mask if pattern[i] is that character
+    ascii_masks: [u64; 128],
+    other_masks: HashMap<char, u64>,
+}
+
+impl Approximate {
+    pub fn new(query: &str, max_distance: usize, ignore_case: bool) -> Approximate {
+        let pattern: Vec<char> = query.chars().map(|c| normalize(c, ignore_case)).collect();
+
+        let mut ascii_masks = [0; 128];
+        let mut other_masks = HashMap::new();
+        // Bitap only has room for 64 characters; longer patterns skip straight to the DP
+        for (i, &c) in pattern.iter().enumerate().take(64) {
+            match ascii_masks.get_mut(c as usize) {
+                Some(mask) => *mask |= 1 << i,
+                None => *other_masks.entry(c).or_insert(0) |= 1 << i,
+            }
+        }
+
+        Approximate { pattern, max_distance, ignore_case, ascii_masks, other_masks }
+    }
+
+    fn mask(&self, c: char) -> u64 {
+        let c = normalize(c, self.ignore_case);
+        match self.ascii_masks.get(c as usize) {
+            Some(&mask) => mask,
+            None => self.other_masks.get(&c).copied().unwrap_or(0),
+        }
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        let len = self.pattern.len();
+        let k = self.max_distance;
+        // Deleting the whole query is within the limit, so anything matches
+        if len <= k {
+            return true;
+        }
+        if len > 64 {
+            return !self.find_all(line).is_empty();
+        }
+
+        // state[d] has bit i set if pattern[..=i] matches some text ending here with at most d edits.
+        // The first d characters can always be deleted, so those bits start (and stay) set.
+        let mut state: Vec<u64> = (0..=k).map(|d| (1 << d) - 1).collect();
+        let goal = 1 << (len - 1);
+
+        for c in line.chars() {
+            let mask = self.mask(c);
+
+            let mut previous = state[0];
+            state[0] = ((state[0] << 1) | 1) & mask;
+            for d in 1..=k {
+                let old = state[d];
+                state[d] = (((old << 1) | 1) & mask)   // c matches the next pattern character
+                    | previous                           // c is an extra character
+                    | (previous << 1) | 1                // c replaces a pattern character
+                    | (state[d - 1] << 1);               // a pattern character is missing
+                previous = old;
+            }
+
+            if state[k] & goal != 0 {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // Where in `line` the matches are, each with as few edits as we could find.
+    // This is Sellers' DP over the pattern, tracking where each partial match started.
+    pub fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        let len = self.pattern.len();
+        let k = self.max_distance;
+        let mut found = Vec::new();
+        if len <= k {
+            return found;
+        }
+
+        // cost[i] is the fewest edits turning pattern[..i] into text ending here, starting at start[i]
+        let mut cost: Vec<usize> = (0..=len).collect();
+        let mut start = vec![0; len + 1];
+        // The best match so far that we haven't reported yet, and its cost
+        let mut best: Option<(usize, Range<usize>)> = None;
+
+        let mut offset = 0;
+        while let Some(c) = line[offset..].chars().next() {
+            let end = offset + c.len_utf8();
+            let c = normalize(c, self.ignore_case);
+
+            let mut diagonal = (cost[0], start[0]);
+            cost[0] = 0;
+            start[0] = end;
+            for i in 1..=len {
+                let substituted = (diagonal.0 + usize::from(self.pattern[i - 1] != c), diagonal.1);
+                let inserted = (cost[i] + 1, start[i]);
+                let deleted = (cost[i - 1] + 1, start[i - 1]);
+                diagonal = (cost[i], start[i]);
+
+                // On a tie, prefer the match that started earlier, so a misspelled word is covered whole
+                (cost[i], start[i]) = [substituted, inserted, deleted].into_iter().min().unwrap();
+            }
+
+            // Keep extending a match for as long as that doesn't make it worse,
+            // then report it and carry on after it
+            match &best {
+                Some((best_cost, _)) if cost[len] <= *best_cost => best = Some((cost[len], start[len]..end)),
+                Some(_) => {
+                    let (_, range) = best.take().unwrap();
+                    offset = range.end;
+                    cost.iter_mut().enumerate().for_each(|(i, cost)| *cost = i);
+                    start.fill(offset);
+                    found.push(range);
+                    continue;
+                }
+                None if cost[len] <= k => best = Some((cost[len], start[len]..end)),
+                None => {}
+            }
+
+            offset = end;
+        }
+
+        found.extend(best.map(|(_, range)| range));
+        found
+    }
+}
+
+// Fuzzy matching (`--fuzzy`): a line matches if it has every character of the query in order,
+// with anything in between, the way fuzzy finders pick files. Matches are scored so the best ones
+// can go first: characters next to each other or at the start of a word count for more, gaps count against.
+pub struct Subsequence {
+    pattern: Vec<char>,
+    ignore_case: bool,
+}
+
+const SCORE_MATCH: i32 = 16;
+const BONUS_BOUNDARY: i32 = 8;
+const BONUS_CONSECUTIVE: i32 = 4;
+const PENALTY_GAP_START: i32 = 3;
+const PENALTY_GAP: i32 = 1;
+
+impl Subsequence {
+    pub fn new(query: &str, ignore_case: bool) -> Subsequence {
+        Subsequence { pattern: query.chars().map(|c| normalize(c, ignore_case)).collect(), ignore_case }
+    }
+
+    // The score and matched characters (merged into ranges) of the best window we could find,
+    // or None if the line doesn't have the whole query.
+    // Like fzf's first algorithm: take the first place the query can end, then walk back from there
+    // to the latest place it can start. That's fast, and it finds the shortest early match,
+    // though not always the best scoring one.
+    pub fn score(&self, line: &str) -> Option<(i32, Vec<Range<usize>>)> {
+        let chars: Vec<(usize, char)> = line.char_indices().collect();
+        let matches = |i: usize, p: usize| normalize(chars[i].1, self.ignore_case) == self.pattern[p];
+
+        // Forward to the earliest end
+        let mut p = 0;
+        let mut end = None;
+        for i in 0..chars.len() {
+            if matches(i, p) {
+                p += 1;
+                if p == self.pattern.len() {
+                    end = Some(i);
+                    break;
+                }
+            }
+        }
+        let end = end?;
+
+        // Back to the latest start
+        let mut start = end;
+        let mut p = self.pattern.len();
+        for i in (0..=end).rev() {
+            if matches(i, p - 1) {
+                p -= 1;
+                if p == 0 {
+                    start = i;
+                    break;
+                }
+            }
+        }
+
+        // Then score the window, matching greedily from its start
+        let mut score = 0;
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        let mut p = 0;
+        let mut previous_matched = false;
+        let mut in_gap = false;
+        for i in start..=end {
+            if p < self.pattern.len() && matches(i, p) {
+                score += SCORE_MATCH;
+                if is_boundary(&chars, i) {
+                    score += BONUS_BOUNDARY;
+                }
+                if previous_matched {
+                    score += BONUS_CONSECUTIVE;
+                }
+
+                let (offset, c) = chars[i];
+                match ranges.last_mut() {
+                    Some(range) if previous_matched => range.end = offset + c.len_utf8(),
+                    _ => ranges.push(offset..offset + c.len_utf8()),
+                }
+
+                p += 1;
+                previous_matched = true;
+                in_gap = false;
+            } else {
+                score -= if in_gap { PENALTY_GAP } else { PENALTY_GAP_START };
+                previous_matched = false;
+                in_gap = true;
+            }
+        }
+
+        Some((score, ranges))
+    }
+}
+
+// Whether chars[i] starts a word: the start of the line, after punctuation or space, or a camelCase hump
+fn is_boundary(chars: &[(usize, char)], i: usize) -> bool {
+    let c = chars[i].1;
+    match i.checked_sub(1).map(|i| chars[i].1) {
+        None => true,
+        Some(before) => !before.is_alphanumeric() && c.is_alphanumeric() || before.is_lowercase() && c.is_uppercase(),
+    }
+}
+
+// Good enough case folding for counting edits: one character for one character,
+// so `ß` and `ss` stay different (though only ever two edits apart)
+fn normalize(c: char, ignore_case: bool) -> char {
+    if !ignore_case {
+        return c;
+    }
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => c,
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found<'a>(query: &str, max_distance: usize, line: &'a str) -> Vec<&'a str> {
+        Approximate::new(query, max_distance, false).find_all(line).into_iter().map(|range| &line[range]).collect()
+    }
+
+    #[test]
+    fn within_edit_distance() {
+        assert_eq!(vec!["conection"], found("connection", 1, "error: conection reset"));
+        assert_eq!(vec!["connectoin"], found("connection", 2, "error: connectoin reset"));
+        assert!(found("connection", 1, "error: connectoin reset").is_empty());
+        assert_eq!(vec!["tmieout", "timeout"], found("timeout", 2, "tmieout, then timeout"));
+        assert!(Approximate::new("TIMEOUT", 1, true).is_match("tmeout"));
+    }
+
+    #[test]
+    fn bitap_agrees_with_the_dp() {
+        // Every short string over a small alphabet, against a few patterns
+        let alphabet = ['a', 'b', 'c'];
+        let mut lines = vec![String::new()];
+        for _ in 0..6 {
+            let longer: Vec<String> = lines.iter()
+                .flat_map(|line| alphabet.iter().map(move |&c| format!("{line}{c}")))
+                .collect();
+            lines.extend(longer);
+        }
+
+        for pattern in ["abc", "abca", "cab"] {
+            for max_distance in 0..3 {
+                let approximate = Approximate::new(pattern, max_distance, false);
+                for line in &lines {
+                    assert_eq!(
+                        !approximate.find_all(line).is_empty(),
+                        approximate.is_match(line),
+                        "{pattern} within {max_distance} of {line}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn subsequences_rank_word_starts_first() {
+        let query = Subsequence::new("rqt", true);
+        let (camel, ranges) = query.score("readQueueTimeout").unwrap();
+        assert_eq!(vec![0..1, 4..5, 9..10], ranges);
+
+        let (scattered, _) = query.score("error: queue is at its limit").unwrap();
+        assert!(camel > scattered);
+        assert!(query.score("no such letters").is_none());
+    }
+}
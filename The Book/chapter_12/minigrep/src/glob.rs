@@ -0,0 +1,65 @@
+// Shell-style wildcards for file types and ignore patterns: `*` is any run of characters
+// and `?` any one character, neither crossing a `/`, and `**` is anything at all.
+// A pattern without a `/` is matched against just the file name, so `*.rs` finds Rust files anywhere.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let path = path.strip_prefix("./").unwrap_or(path);
+    let path = if pattern.contains('/') {
+        path
+    } else {
+        path.rsplit('/').next().unwrap_or(path)
+    };
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    matches_from(&pattern, &path)
+}
+
+fn matches_from(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `**/` can also stand for no directories at all
+            if let ['/', after @ ..] = rest
+                && matches_from(after, path)
+            {
+                return true;
+            }
+            (0..=path.len()).any(|skip| matches_from(rest, &path[skip..]))
+        }
+        ['*', rest @ ..] => {
+            // As many characters as we like, up to the next `/`
+            let limit = path.iter().position(|&c| c == '/').unwrap_or(path.len());
+            (0..=limit).any(|skip| matches_from(rest, &path[skip..]))
+        }
+        ['?', rest @ ..] => matches!(path, [c, ..] if *c != '/') && matches_from(rest, &path[1..]),
+        [c, rest @ ..] => path.first() == Some(c) && matches_from(rest, &path[1..]),
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        assert!(matches("*.rs", "src/lib.rs"));
+        assert!(matches("*.rs", "lib.rs"));
+        assert!(!matches("*.rs", "lib.rs.orig"));
+        assert!(matches("Cargo.????", "./Cargo.lock"));
+        assert!(!matches("*.log", "logs/app.log.gz"));
+    }
+
+    #[test]
+    fn paths() {
+        assert!(matches("target/*", "target/debug"));
+        assert!(!matches("target/*", "target/debug/minigrep"));
+        assert!(matches("target/**", "target/debug/minigrep"));
+        assert!(matches("**/*.min.js", "static/js/app.min.js"));
+        assert!(matches("**/*.min.js", "app.min.js"));
+        assert!(!matches("src/*.rs", "tests/cli.rs"));
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+use std::time::UNIX_EPOCH;
+
+use crate::expr::{self, Expr};
+use crate::fold::fold_str;
+use crate::input;
+
+// A trigram index of a directory tree, kept in `.minigrep-index` at its top.
+//
+//   minigrep --index-build [DIR]    index every file under DIR (default .) from scratch
+//   minigrep --index-update [DIR]   re-read only the files that changed since, and drop deleted ones
+//   minigrep --index QUERY [DIR]    search just the files that could contain QUERY
+//
+// Building and updating are flags rather than words like `index build`, which would stop anyone
+// searching a file called `build` for "index".
+//
+// For each file we keep every run of three bytes in its folded text (see `fold_str`). A file can
+// only contain a query if it has all of the query's trigrams, so that rules out most of a big
+// tree before anything is opened, and whatever's left gets the normal search.
+// Folding means the same index works with and without IGNORE_CASE: text that matches exactly
+// still matches once both sides are folded.
+pub const FILE_NAME: &str = ".minigrep-index";
+
+// Bumped whenever the layout below changes, so an old index is rebuilt rather than misread
+const MAGIC: &[u8] = b"minigrep-index 2\n";
+
+#[derive(Debug, PartialEq)]
+struct Entry {
+    // Relative to the index's directory, with `/` between the parts
+    path: String,
+    // Modification time (seconds, nanoseconds) and size when we read it, to tell if it's changed
+    modified: (u64, u32),
+    size: u64,
+    // Binary files are never searched, so all we keep is enough to tell they haven't changed
+    binary: bool,
+    // Sorted, each one three bytes in the low 24 bits
+    trigrams: Vec<u32>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Index {
+    entries: Vec<Entry>,
+}
+
+// `minigrep --index-build|--index-update [DIR]`; `args` starts with the flag
+pub fn command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (rebuild, root) = match args {
+        [action, rest @ ..] if rest.len() <= 1 => {
+            let root = rest.first().map_or(".", String::as_str);
+            match action.as_str() {
+                "--index-build" => (true, root),
+                "--index-update" => (false, root),
+                _ => return Err(format!("Unknown index command {action:?}; expected --index-build or --index-update").into()),
+            }
+        }
+        _ => return Err("Usage: minigrep --index-build|--index-update [DIR]".into()),
+    };
+
+    let old = if rebuild { Index::default() } else { Index::load(root)?.unwrap_or_default() };
+    let (index, reread) = Index::refresh(root, old)?;
+    index.save(root)?;
+
+    let indexed = index.entries.iter().filter(|entry| !entry.binary).count();
+    println!("{indexed} files indexed, {reread} read");
+    Ok(())
+}
+
+// The files under `roots` that the index says could match. Files that have changed since they were
+// indexed are always included, since what we know about them is out of date; files added since
+// aren't, so run `minigrep --index-update` after adding files.
+//
+// `narrow` is false for searches that don't need the query's text to be there (fuzzy or
+// approximate ones), which only get the list of files.
+pub fn candidates(roots: &[String], queries: &[String], expr: bool, narrow: bool) -> Result<Vec<String>, String> {
+    // Each query's trigrams. If any query has none (it's too short), it could match anywhere.
+    let query = if expr { Some(expr::parse(&queries[0]).map_err(|e| e.to_string())?) } else { None };
+    let terms = match &query {
+        Some(query) => &query.terms,
+        None => queries,
+    };
+    let needed: Vec<Vec<u32>> = terms.iter().map(|term| trigrams(fold_str(term).as_bytes())).collect();
+
+    let mut paths = Vec::new();
+    for root in roots {
+        let index = Index::load(root)
+            .map_err(|e| format!("{root}/{FILE_NAME}: {e}"))?
+            .ok_or_else(|| format!("{root} has no index; run `minigrep --index-build {root}` first"))?;
+
+        for entry in &index.entries {
+            let path = join(root, &entry.path);
+            let Ok(metadata) = fs::metadata(&path) else {
+                // Deleted since
+                continue;
+            };
+            let changed = (modified(&metadata), metadata.len()) != (entry.modified, entry.size);
+            if entry.binary && !changed {
+                continue;
+            }
+
+            let has = |trigrams: &Vec<u32>| trigrams.iter().all(|t| entry.trigrams.binary_search(t).is_ok());
+            let present: Vec<bool> = needed.iter().map(has).collect();
+            let possible = match &query {
+                Some(query) => possible(&query.expr, &present),
+                None => present.iter().any(|&present| present),
+            };
+
+            if changed || !narrow || possible {
+                paths.push(path);
+            }
+        }
+    }
+
+    Ok(paths)
+}
+
+// Whether a line matching `expr` could be in the file, given which terms could be.
+// A file that could contain a term could also lack it, so NOT rules nothing out.
+fn possible(expr: &Expr, present: &[bool]) -> bool {
+    match expr {
+        Expr::Term(i) => present[*i],
+        Expr::Not(_) => true,
+        Expr::And(left, right) => possible(left, present) && possible(right, present),
+        Expr::Or(left, right) => possible(left, present) || possible(right, present),
+    }
+}
+
+// Every distinct trigram in `bytes`, sorted
+fn trigrams(bytes: &[u8]) -> Vec<u32> {
+    let set: HashSet<u32> = bytes.windows(3)
+        .map(|w| u32::from(w[0]) << 16 | u32::from(w[1]) << 8 | u32::from(w[2]))
+        .collect();
+    let mut trigrams: Vec<u32> = set.into_iter().collect();
+    trigrams.sort_unstable();
+    trigrams
+}
+
+// `path` under `root`, without a leading `./` when `root` is the current directory
+pub fn join(root: &str, path: &str) -> String {
+    match root.trim_end_matches('/') {
+        "." => path.to_string(),
+        "" => format!("/{path}"),
+        root => format!("{root}/{path}"),
+    }
+}
+
+// Seconds and nanoseconds since the epoch, or zero where the platform doesn't record it
+pub fn modified(metadata: &fs::Metadata) -> (u64, u32) {
+    metadata.modified().ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or((0, 0), |since| (since.as_secs(), since.subsec_nanos()))
+}
+
+// Every file under `root`, relative to it and sorted by path like the index is.
+// That isn't quite the order we walk in, since `a.txt` sorts before `a/b`.
+pub fn files_under(root: &str) -> io::Result<Vec<String>> {
+    let mut paths = Vec::new();
+    walk(root, ".", &mut paths)?;
+    paths.sort();
+    Ok(paths)
+}
+
+// Every regular file under `dir`, skipping hidden files and directories (`.git`, the index itself)
+// and not following symlinks, so a link back up the tree can't send us round in circles
+fn walk(root: &str, dir: &str, files: &mut Vec<String>) -> io::Result<()> {
+    let mut entries = fs::read_dir(join(root, dir))?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = if dir == "." { name } else { format!("{dir}/{name}") };
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(root, &path, files)?;
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+impl Index {
+    // The index for everything under `root` now, reusing what `old` has for files that haven't
+    // changed. Also says how many files had to be read. A file we can't read is left out with a
+    // warning, and tried again next time.
+    fn refresh(root: &str, old: Index) -> io::Result<(Index, usize)> {
+        let paths = files_under(root)?;
+        let mut old = old.entries.into_iter().peekable();
+        let mut index = Index::default();
+        let mut reread = 0;
+
+        // Both lists are sorted by path, so we can walk them side by side
+        for path in paths {
+            while old.next_if(|entry| entry.path < path).is_some() {}
+
+            let full_path = join(root, &path);
+            let metadata = match fs::metadata(&full_path) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    eprintln!("{full_path}: {e}");
+                    continue;
+                }
+            };
+            let (modified, size) = (modified(&metadata), metadata.len());
+            if let Some(entry) = old.next_if(|entry| entry.path == path && entry.modified == modified && entry.size == size) {
+                index.entries.push(entry);
+                continue;
+            }
+
+            let mut bytes = Vec::new();
+            reread += 1;
+            let read = input::open_text(&full_path, false, None).and_then(|mut input| input.reader.read_to_end(&mut bytes));
+            if let Err(e) = read {
+                eprintln!("{full_path}: {e}");
+                continue;
+            }
+
+            // Binary files aren't worth indexing, and would only make the index bigger
+            let binary = bytes.contains(&0);
+            let trigrams = if binary { Vec::new() } else { trigrams(fold_str(&String::from_utf8_lossy(&bytes)).as_bytes()) };
+            index.entries.push(Entry { path, modified, size, binary, trigrams });
+        }
+
+        Ok((index, reread))
+    }
+
+    // None if there's no index there yet
+    fn load(root: &str) -> io::Result<Option<Index>> {
+        match fs::read(join(root, FILE_NAME)) {
+            Ok(bytes) => Index::decode(&bytes).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Written to a temporary file and renamed into place, so a search never sees half an index.
+    // The pid keeps two updates from sharing the temporary file, and the sync makes sure the
+    // data is on disk before the rename is, so a crash can't leave an empty index behind
+    fn save(&self, root: &str) -> io::Result<()> {
+        let path = join(root, FILE_NAME);
+        let temporary = format!("{path}.{}.tmp", process::id());
+        let written = fs::File::create(&temporary).and_then(|mut file| {
+            file.write_all(&self.encode())?;
+            file.sync_all()
+        });
+        match written.and_then(|()| fs::rename(&temporary, &path)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&temporary);
+                Err(e)
+            }
+        }
+    }
+
+    // MAGIC, then for each file (numbers little-endian):
+    //   u32 path length, path, u64 seconds, u32 nanoseconds, u64 size, u8 1 if binary or else 0,
+    //   u32 trigram count, then three bytes for each trigram
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for entry in &self.entries {
+            bytes.extend((entry.path.len() as u32).to_le_bytes());
+            bytes.extend(entry.path.as_bytes());
+            bytes.extend(entry.modified.0.to_le_bytes());
+            bytes.extend(entry.modified.1.to_le_bytes());
+            bytes.extend(entry.size.to_le_bytes());
+            bytes.push(u8::from(entry.binary));
+            bytes.extend((entry.trigrams.len() as u32).to_le_bytes());
+            for trigram in &entry.trigrams {
+                bytes.extend(&trigram.to_be_bytes()[1..]);
+            }
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Index> {
+        let mut rest = bytes.strip_prefix(MAGIC).ok_or_else(corrupt)?;
+        let rest = &mut rest;
+
+        let mut index = Index::default();
+        while !rest.is_empty() {
+            let len = u32::from_le_bytes(take(rest)?) as usize;
+            let path = String::from_utf8(take_slice(rest, len)?.to_vec()).map_err(|_| corrupt())?;
+            let seconds = u64::from_le_bytes(take(rest)?);
+            let nanoseconds = u32::from_le_bytes(take(rest)?);
+            let size = u64::from_le_bytes(take(rest)?);
+            let binary = match take(rest)? {
+                [0] => false,
+                [1] => true,
+                _ => return Err(corrupt()),
+            };
+            let count = u32::from_le_bytes(take(rest)?) as usize;
+            let trigrams = take_slice(rest, count.checked_mul(3).ok_or_else(corrupt)?)?
+                .chunks(3)
+                .map(|t| u32::from(t[0]) << 16 | u32::from(t[1]) << 8 | u32::from(t[2]))
+                .collect();
+            index.entries.push(Entry { path, modified: (seconds, nanoseconds), size, binary, trigrams });
+        }
+        Ok(index)
+    }
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "index is corrupt or from another version; rebuild it")
+}
+
+// The next `len` bytes of `rest`, moving past them
+fn take_slice<'a>(rest: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    let (taken, after) = rest.split_at_checked(len).ok_or_else(corrupt)?;
+    *rest = after;
+    Ok(taken)
+}
+
+// The next N bytes as an array, for `from_le_bytes`
+fn take<const N: usize>(rest: &mut &[u8]) -> io::Result<[u8; N]> {
+    Ok(take_slice(rest, N)?.try_into().unwrap())
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn trigrams_are_sorted_and_distinct() {
+        assert_eq!(vec![0x616161], trigrams(b"aaaaa"));
+        assert_eq!(vec![0x616263, 0x626364], trigrams(b"abcd"));
+        assert!(trigrams(b"ab").is_empty());
+    }
+
+    #[test]
+    fn round_trip() {
+        let index = Index {
+            entries: vec![
+                Entry { path: "src/lib.rs".to_string(), modified: (1_700_000_000, 5), size: 42, binary: false, trigrams: vec![0x616263, 0xffffff] },
+                Entry { path: "empty".to_string(), modified: (0, 0), size: 0, binary: false, trigrams: Vec::new() },
+                Entry { path: "logo.png".to_string(), modified: (1, 2), size: 3, binary: true, trigrams: Vec::new() },
+            ],
+        };
+        let bytes = index.encode();
+        assert_eq!(index, Index::decode(&bytes).unwrap());
+        assert!(Index::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Index::decode(b"something else").is_err());
+    }
+
+    #[test]
+    fn narrows_and_updates() {
+        let dir = env::temp_dir().join(format!("minigrep-index-{}", process::id()));
+        let root = dir.to_string_lossy().into_owned();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("src/one.txt"), "the quick brown fox\n").unwrap();
+        fs::write(dir.join("src/two.txt"), "jumps over the lazy dog\n").unwrap();
+        fs::write(dir.join("binary"), b"fox\0").unwrap();
+        fs::write(dir.join(".git/config"), "fox").unwrap();
+
+        let (index, reread) = Index::refresh(&root, Index::default()).unwrap();
+        assert_eq!(3, reread);
+        index.save(&root).unwrap();
+        let paths: Vec<(&str, bool)> = index.entries.iter().map(|entry| (entry.path.as_str(), entry.binary)).collect();
+        assert_eq!(vec![("binary", true), ("src/one.txt", false), ("src/two.txt", false)], paths);
+
+        let roots = [root.clone()];
+        let search = |queries: &[&str], expr| {
+            let queries: Vec<String> = queries.iter().map(|q| q.to_string()).collect();
+            candidates(&roots, &queries, expr, true).unwrap()
+        };
+        let one = join(&root, "src/one.txt");
+        let two = join(&root, "src/two.txt");
+        assert_eq!(vec![one.clone()], search(&["Brown"], false));
+        assert_eq!(vec![one.clone(), two.clone()], search(&["fox", "lazy"], false));
+        assert_eq!(vec![one.clone(), two.clone()], search(&["ox"], false));
+        assert!(search(&["zebra"], false).is_empty());
+        assert_eq!(vec![two.clone()], search(&["the AND NOT zebra AND lazy"], true));
+
+        // A changed file is searched whatever the index says, until the index is updated. The
+        // binary file hasn't changed, so it isn't read again.
+        fs::write(dir.join("src/one.txt"), "a zebra, now\n").unwrap();
+        fs::remove_file(dir.join("src/two.txt")).unwrap();
+        assert_eq!(vec![one.clone()], search(&["zebra"], false));
+        let (index, reread) = Index::refresh(&root, Index::load(&root).unwrap().unwrap()).unwrap();
+        assert_eq!(1, reread);
+        index.save(&root).unwrap();
+        assert!(search(&["fox"], false).is_empty());
+        assert_eq!(vec![one], search(&["zebra"], false));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_files_it_cant_read() {
+        let dir = env::temp_dir().join(format!("minigrep-index-unreadable-{}", process::id()));
+        let root = dir.to_string_lossy().into_owned();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("broken.gz"), "not gzip at all\n").unwrap();
+        fs::write(dir.join("fine.txt"), "the quick brown fox\n").unwrap();
+
+        let (index, reread) = Index::refresh(&root, Index::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(2, reread);
+        let paths: Vec<&str> = index.entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(vec!["fine.txt"], paths);
+    }
+}
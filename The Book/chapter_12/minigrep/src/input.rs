@@ -0,0 +1,206 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use crate::decompress::{Decompressor, Format};
+use crate::encoding::{Decoder, Encoding, Source};
+use crate::Match;
+
+// How much we try to read before handing lines off to be searched.
+// Memory use stays around this size however big the input is, unless a single line is longer.
+pub const BLOCK_SIZE: usize = 256 * 1024;
+
+// `-` means stdin, so minigrep can sit at the end of a pipeline.
+// Files named like compressed files are decompressed on the way in. With `search_zip` (`-z`),
+// anything starting with a compressed format's magic bytes is too, stdin included.
+pub fn open(path: &str, search_zip: bool) -> io::Result<Box<dyn BufRead>> {
+    let mut reader: Box<dyn BufRead + Send> = if path == "-" {
+        Box::new(BufReader::with_capacity(BLOCK_SIZE, io::stdin()))
+    } else {
+        Box::new(BufReader::with_capacity(BLOCK_SIZE, File::open(path)?))
+    };
+
+    let format = match Format::from_extension(path) {
+        Some(format) => Some(format),
+        // Peeking at the buffer doesn't consume it, so the decompressor still gets the whole input
+        None if search_zip => Format::from_magic(reader.fill_buf()?),
+        None => None,
+    };
+
+    match format {
+        Some(format) => Ok(Box::new(BufReader::with_capacity(BLOCK_SIZE, Decompressor::spawn(format, reader)?))),
+        None => Ok(reader),
+    }
+}
+
+// Input as text: UTF-8, whatever it was in the file, along with where that text came from
+pub struct Input {
+    pub reader: Box<dyn BufRead>,
+    pub source: Source,
+}
+
+// Like `open`, but decoded from `encoding`. Without one, a byte order mark decides, and
+// anything without one is taken to be UTF-8. The byte order mark itself is skipped either way.
+pub fn open_text(path: &str, search_zip: bool, encoding: Option<Encoding>) -> io::Result<Input> {
+    let mut reader = open(path, search_zip)?;
+
+    let (encoding, start) = match (Encoding::sniff(reader.fill_buf()?), encoding) {
+        (Some((sniffed, len)), None) => (sniffed, len),
+        (Some((sniffed, len)), Some(encoding)) if sniffed == encoding => (encoding, len),
+        (_, encoding) => (encoding.unwrap_or_default(), 0),
+    };
+    reader.consume(start);
+
+    let source = Source { encoding, start };
+    if encoding == Encoding::Utf8 {
+        return Ok(Input { reader, source });
+    }
+    Ok(Input { reader: Box::new(BufReader::with_capacity(BLOCK_SIZE, Decoder::new(reader, encoding))), source })
+}
+
+// A run of whole lines read from the input
+pub struct Block<'a> {
+    pub contents: &'a str,
+    // Number of lines that came before this block
+    pub line_offset: usize,
+    // Byte offset of the start of this block in the original input
+    pub byte_offset: usize,
+    // Where each line starts in the original input, when the input wasn't UTF-8 and
+    // offsets into `contents` aren't offsets into the file
+    pub line_starts: Option<&'a [usize]>,
+}
+
+impl<'a> Block<'a> {
+    // Run a search over this block, fixing up the positions so they're relative to the whole input
+    pub fn search<F>(&self, search: F) -> Vec<Match<'a>>
+    where
+        F: FnOnce(&'a str) -> Vec<Match<'a>>,
+    {
+        let mut results = search(self.contents);
+        for m in &mut results {
+            self.relocate(m);
+        }
+        results
+    }
+
+    // Move a match found in `contents` to where it is in the whole input
+    pub fn relocate(&self, m: &mut Match) {
+        m.byte_offset = self.original_offset(m.line_number - 1, m.byte_offset);
+        m.line_number += self.line_offset;
+    }
+
+    // Where the line `index` lines into the block, `offset` bytes into `contents`, starts in the input
+    pub fn original_offset(&self, index: usize, offset: usize) -> usize {
+        match self.line_starts {
+            Some(starts) => starts[index],
+            None => self.byte_offset + offset,
+        }
+    }
+}
+
+// Read `reader` a block at a time, always splitting on line boundaries.
+// Invalid UTF-8 is replaced with U+FFFD rather than failing the whole search. Line offsets are
+// still taken from the bytes that were read, so only offsets inside a line with bad bytes in it
+// are approximate.
+// `source` says what the text was decoded from, so offsets can be given in terms of the file.
+pub fn for_each_block<R, F>(mut reader: R, source: Source, block_size: usize, mut f: F) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(&Block) -> io::Result<()>,
+{
+    let mut buf = Vec::with_capacity(block_size);
+    let mut line_offset = 0;
+    let mut byte_offset = source.start;
+
+    loop {
+        buf.clear();
+        while buf.len() < block_size {
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+        }
+
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let contents: Cow<str> = String::from_utf8_lossy(&buf);
+        let line_starts = source.line_starts(&buf, &contents, byte_offset);
+        f(&Block { contents: &contents, line_offset, byte_offset, line_starts: line_starts.as_deref() })?;
+
+        line_offset += buf.iter().filter(|&&b| b == b'\n').count();
+        byte_offset += source.original_len(&buf, &contents);
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search;
+
+    #[test]
+    fn splits_on_line_boundaries() {
+        let input = "one\ntwo\nthree\nfour";
+        let mut blocks = Vec::new();
+
+        for_each_block(input.as_bytes(), Source::default(), 5, |block| {
+            blocks.push((block.contents.to_string(), block.line_offset, block.byte_offset));
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(vec![
+            ("one\ntwo\n".to_string(), 0, 0),
+            ("three\n".to_string(), 2, 8),
+            ("four".to_string(), 3, 14),
+        ], blocks);
+    }
+
+    #[test]
+    fn positions_span_blocks() {
+        let input = "a\nb match\nc\nd match\n";
+        let mut found = Vec::new();
+
+        for_each_block(input.as_bytes(), Source::default(), 1, |block| {
+            for m in block.search(|contents| search("match", contents)) {
+                found.push((m.line_number, m.byte_offset, m.line.to_string()));
+            }
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(vec![(2, 2, "b match".to_string()), (4, 12, "d match".to_string())], found);
+    }
+
+    #[test]
+    fn tolerates_invalid_utf8() {
+        let input: &[u8] = b"caf\xe9 match\nplain\n";
+        let mut found = Vec::new();
+
+        for_each_block(input, Source::default(), BLOCK_SIZE, |block| {
+            for m in block.search(|contents| search("match", contents)) {
+                found.push(m.line.to_string());
+            }
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(vec!["caf\u{FFFD} match".to_string()], found);
+    }
+
+    #[test]
+    fn offsets_count_invalid_bytes_once() {
+        let input: &[u8] = b"caf\xe9\nmatch\n\xff\xfe\nmatch\n";
+        let mut found = Vec::new();
+
+        for_each_block(input, Source::default(), 8, |block| {
+            for m in block.search(|contents| search("match", contents)) {
+                found.push((m.line_number, m.byte_offset));
+            }
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(vec![(2, 5), (4, 14)], found);
+    }
+}
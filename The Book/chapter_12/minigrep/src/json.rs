@@ -0,0 +1,142 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::{Stats, Summary};
+
+// `--json` writes one of these records per line (JSON Lines):
+//
+//   {"type":"begin","path":"poem.txt"}
+//   {"type":"match","path":"poem.txt","line_number":1,"byte_offset":0,"line":"...","submatches":[{"text":"nobody","start":4,"end":10}]}
+//   {"type":"context","path":"poem.txt","line_number":3,"byte_offset":46,"line":"..."}
+//   {"type":"end","path":"poem.txt","matched_lines":2,"matches":2}
+//   {"type":"summary","files_searched":1,"files_with_matches":1,"files_failed":0,"matched_lines":2,"matches":2,"elapsed_ms":0.412}
+//
+// The reports (`--stats`, `--histogram`, `--top`) have an end record for each file with matches, then
+//
+//   {"type":"histogram","time":"2024-05-01 10:00","matched_lines":2}   (time is null for lines without one)
+//   {"type":"top","text":"timeout","count":3}
+//
+// and the summary.
+//
+// `byte_offset` is where the line starts in the file; submatch `start` and `end` are byte offsets into `line`.
+// Files without any matches don't get begin or end records.
+
+// Quote and escape `text` as a JSON string
+pub fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub fn begin<W: Write>(out: &mut W, path: &str) -> io::Result<()> {
+    writeln!(out, r#"{{"type":"begin","path":{}}}"#, string(path))
+}
+
+// `submatches` is None for context lines
+pub fn line<W: Write>(
+    out: &mut W,
+    path: &str,
+    line_number: usize,
+    byte_offset: usize,
+    line: &str,
+    submatches: Option<&[Range<usize>]>,
+) -> io::Result<()> {
+    let kind = if submatches.is_some() { "match" } else { "context" };
+    write!(
+        out,
+        r#"{{"type":"{kind}","path":{},"line_number":{line_number},"byte_offset":{byte_offset},"line":{}"#,
+        string(path),
+        string(line)
+    )?;
+
+    if let Some(submatches) = submatches {
+        let submatches: Vec<String> = submatches.iter()
+            .map(|range| format!(
+                r#"{{"text":{},"start":{},"end":{}}}"#,
+                string(&line[range.clone()]),
+                range.start,
+                range.end
+            ))
+            .collect();
+        write!(out, r#","submatches":[{}]"#, submatches.join(","))?;
+    }
+
+    writeln!(out, "}}")
+}
+
+pub fn end<W: Write>(out: &mut W, path: &str, stats: &Stats) -> io::Result<()> {
+    writeln!(
+        out,
+        r#"{{"type":"end","path":{},"matched_lines":{},"matches":{}}}"#,
+        string(path),
+        stats.matched_lines,
+        stats.matches
+    )
+}
+
+pub fn histogram<W: Write>(out: &mut W, time: Option<&str>, matched_lines: usize) -> io::Result<()> {
+    let time = time.map_or("null".to_string(), string);
+    writeln!(out, r#"{{"type":"histogram","time":{time},"matched_lines":{matched_lines}}}"#)
+}
+
+pub fn top<W: Write>(out: &mut W, text: &str, count: usize) -> io::Result<()> {
+    writeln!(out, r#"{{"type":"top","text":{},"count":{count}}}"#, string(text))
+}
+
+pub fn summary<W: Write>(out: &mut W, summary: &Summary, elapsed: Duration) -> io::Result<()> {
+    writeln!(
+        out,
+        r#"{{"type":"summary","files_searched":{},"files_with_matches":{},"files_failed":{},"matched_lines":{},"matches":{},"elapsed_ms":{:.3}}}"#,
+        summary.files_searched,
+        summary.files_with_matches,
+        summary.files_failed,
+        summary.stats.matched_lines,
+        summary.stats.matches,
+        elapsed.as_secs_f64() * 1000.0
+    )
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(r#""plain""#, string("plain"));
+        assert_eq!(r#""say \"hi\"\\n""#, string("say \"hi\"\\n"));
+        assert_eq!(r#""tab\there\u001b[0m""#, string("tab\there\x1b[0m"));
+        assert_eq!(r#""straße""#, string("straße"));
+    }
+
+    #[test]
+    fn match_record() {
+        let mut out = Vec::new();
+        line(&mut out, "poem.txt", 2, 25, "Are you nobody, too?", Some(&[Range { start: 8, end: 14 }])).unwrap();
+        assert_eq!(
+            concat!(
+                r#"{"type":"match","path":"poem.txt","line_number":2,"byte_offset":25,"line":"Are you nobody, too?","#,
+                r#""submatches":[{"text":"nobody","start":8,"end":14}]}"#,
+                "\n"
+            ),
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
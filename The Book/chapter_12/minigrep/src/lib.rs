@@ -1,65 +1,656 @@
 use std::error::Error;
-use std::fs;
 use std::env;
+use std::io::{self, IsTerminal, Read, Write};
+use std::ops::Range;
+use std::thread;
+use std::time::Instant;
+
+mod config_file;
+mod decompress;
+mod encoding;
+mod expr;
+mod fold;
+mod follow;
+mod fuzzy;
+mod glob;
+mod index;
+mod input;
+mod json;
+mod literal;
+mod matcher;
+mod printer;
+mod replace;
+mod report;
+mod tui;
+mod workers;
+
+use config_file::ConfigFile;
+use encoding::Encoding;
+use input::{Block, Input};
+use matcher::Matcher;
+use printer::Printer;
+use replace::Template;
+use report::Bucket;
 
 pub struct Config {
-    pub query: String,
-    pub file_path: String,
+    // Usually just the one; `-e` can be given several times to match any of them
+    pub queries: Vec<String>,
+    pub file_paths: Vec<String>,
     pub ignore_case: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub threads: usize,
+    // Print files in the order they were given instead of the order they finish in
+    pub sort: bool,
+    pub line_numbers: bool,
+    // Whether to highlight output with ANSI colors, already resolved from `--color` and the terminal
+    pub color: bool,
+    // Write JSON Lines records instead of text, for other programs to read
+    pub json: bool,
+    // `--replace`: print matching lines with each match replaced by this template
+    pub replace: Option<String>,
+    // With `--replace`, write the replacements back to the files instead of printing lines
+    pub in_place: bool,
+    // With `--replace`, print a unified diff of what would change instead
+    pub diff: bool,
+    // Match text within this many edits of a query, rather than exactly
+    pub max_distance: Option<usize>,
+    // Match lines with the characters of a query in order, best matches first
+    pub fuzzy: bool,
+    // Decompress anything that looks compressed, not just files with a compressed extension
+    pub search_zip: bool,
+    // The query is a boolean expression of terms, like `error AND NOT timeout`
+    pub expr: bool,
+    // Let matches run across line breaks, which can be written `\n` in the query
+    pub multiline: bool,
+    // Browse the results in the terminal instead of printing them
+    pub interactive: bool,
+    // The paths were directories with an index, and `file_paths` are the files in them worth searching
+    pub index: bool,
+    // Keep printing new matching lines as the files grow, like `tail -F | grep`
+    pub follow: bool,
+    // Search again whenever the files change; directories stand for every file under them
+    pub watch: bool,
+    // From `--type` and the config file, for the files `--watch` finds in directories
+    pub type_globs: Vec<String>,
+    pub ignore: Vec<String>,
+    // What the files are encoded in; None to go by their byte order marks, or UTF-8 without one
+    pub encoding: Option<Encoding>,
+    // Print totals instead of lines (`--stats`), maybe with a histogram over time and the most common matches
+    pub report: bool,
+    pub histogram: Option<Bucket>,
+    pub top: Option<usize>,
 }
 
 impl Config {
-    pub fn build(args: &[String]) -> Result<Config, &'static str> {
-        if args.len() < 3 { return Err("Not enough arguments"); }
+    // Settings come from, in increasing order of priority: built-in defaults, the config file,
+    // the environment (IGNORE_CASE, NO_COLOR) and the command line. `--no-config` skips the file,
+    // and the `--no-...` forms of the on/off flags undo them, when the file turns them on.
+    pub fn build(args: &[String]) -> Result<Config, String> {
+        let file = if args.iter().any(|arg| arg == "--no-config") {
+            ConfigFile::default()
+        } else {
+            ConfigFile::load().map_err(|e| format!("Bad config file {e}"))?
+        };
+        Config::build_with(args, &file)
+    }
+
+    fn build_with(args: &[String], file: &ConfigFile) -> Result<Config, String> {
+        let mut positional = Vec::new();
+        let mut queries = Vec::new();
+        let mut before_context = None;
+        let mut after_context = None;
+        let mut context = None;
+        let mut threads = None;
+        let mut sort = false;
+        let mut line_numbers = false;
+        let mut color = "auto";
+        let mut color_on_command_line = false;
+        let mut json = false;
+        let mut replace = None;
+        let mut in_place = false;
+        let mut diff = false;
+        let mut max_distance = None;
+        let mut fuzzy = false;
+        let mut search_zip = false;
+        let mut expr = false;
+        let mut types = Vec::new();
+        let mut multiline = false;
+        let mut interactive = false;
+        let mut index = false;
+        let mut follow = false;
+        let mut watch = false;
+        let mut encoding = None;
+        let mut report = false;
+        let mut histogram = None;
+        let mut top = None;
+
+        // The config file's flags go first so that anything on the command line overrides them.
+        // Skip the program name, then pull flags out from wherever they appear.
+        let all_args: Vec<&String> = file.flags.iter().chain(args.iter().skip(1)).collect();
+        let mut args = all_args.iter().copied();
+        while let Some(arg) = args.next() {
+            let from_file = all_args.len() - args.len() <= file.flags.len();
+            match arg.as_str() {
+                "-A" => after_context = Some(parse_context(args.next())?),
+                "-B" => before_context = Some(parse_context(args.next())?),
+                "-C" => context = Some(parse_context(args.next())?),
+                "-e" => queries.push(args.next().ok_or("Missing pattern after -e")?.clone()),
+                "-j" | "--threads" => threads = Some(parse_threads(args.next())?),
+                "--sort" => sort = true,
+                "--no-sort" => sort = false,
+                "-n" | "--line-number" => line_numbers = true,
+                "--no-line-number" => line_numbers = false,
+                "--color" => {
+                    color = "auto";
+                    color_on_command_line = !from_file;
+                }
+                "--json" => json = true,
+                "--no-json" => json = false,
+                "--replace" => replace = Some(args.next().ok_or("Missing replacement after --replace")?.clone()),
+                "--in-place" => in_place = true,
+                "--diff" => diff = true,
+                "--max-distance" => max_distance = Some(parse_distance(args.next())?),
+                "--fuzzy" => fuzzy = true,
+                "--no-fuzzy" => fuzzy = false,
+                "-z" | "--search-zip" => search_zip = true,
+                "--no-search-zip" => search_zip = false,
+                "--expr" => expr = true,
+                "--no-expr" => expr = false,
+                "-t" | "--type" => types.push(args.next().ok_or("Missing file type after --type")?),
+                "-U" | "--multiline" => multiline = true,
+                "--no-multiline" => multiline = false,
+                "--tui" => interactive = true,
+                "--index" => index = true,
+                "-f" | "--follow" => follow = true,
+                "--watch" => watch = true,
+                "--encoding" => encoding = parse_encoding(args.next())?,
+                "--stats" => report = true,
+                "--no-stats" => {
+                    report = false;
+                    histogram = None;
+                    top = None;
+                }
+                "--histogram" => {
+                    let unit = args.next().ok_or("Missing unit after --histogram")?;
+                    histogram = Some(Bucket::from_name(unit).ok_or("--histogram must be one of minute, hour or day")?);
+                }
+                "--top" => top = Some(parse_top(args.next())?),
+                "--no-config" => {}
+                _ => match arg.strip_prefix("--color=") {
+                    Some(when) => {
+                        color = when;
+                        color_on_command_line = !from_file;
+                    }
+                    // Patterns and paths belong on the command line
+                    None if from_file => {
+                        return Err(format!("Config file flags can't include {arg:?}"));
+                    }
+                    None => positional.push(arg),
+                },
+            }
+        }
+
+        // Without any `-e`, the first argument is the query
+        if queries.is_empty() {
+            if positional.is_empty() { return Err("Not enough arguments".into()); }
+            queries.push(positional.remove(0).clone());
+        }
+
+        // No paths (or `-`) means read from stdin, or with `--index`, the current directory's index
+        let mut file_paths: Vec<String> = positional.iter().map(|path| path.to_string()).collect();
+        if file_paths.is_empty() {
+            file_paths.push(if index { "." } else { "-" }.to_string());
+        }
+        if index && file_paths.iter().any(|path| path == "-") {
+            return Err("--index needs indexed directories, not stdin".into());
+        }
+        // The index is built from the files read as UTF-8 (or whatever their byte order mark
+        // says), so its trigrams would rule out files that only match in another encoding
+        if index && encoding.is_some_and(|encoding| encoding != Encoding::Utf8) {
+            return Err("--index can't be used with --encoding".into());
+        }
+
+        if let Some(template) = &replace {
+            // Catch a bad template now rather than once per file
+            Template::new(template)?;
+        } else if in_place || diff {
+            return Err("--in-place and --diff only make sense with --replace".into());
+        }
+        if in_place && diff {
+            return Err("--in-place and --diff can't be used together".into());
+        }
+        // Rewriting would have to encode the file again, and we only write UTF-8
+        if (in_place || diff) && encoding.is_some_and(|encoding| encoding != Encoding::Utf8) {
+            return Err("--in-place and --diff only work on UTF-8 files".into());
+        }
+        if in_place && file_paths.iter().any(|path| path == "-") {
+            return Err("--in-place needs files to rewrite, not stdin".into());
+        }
+
+        if expr {
+            if queries.len() > 1 || fuzzy || max_distance.is_some() {
+                return Err("--expr takes a single query, and can't be used with --fuzzy or --max-distance".into());
+            }
+            expr::parse(&queries[0]).map_err(|e| format!("Bad query expression at {e}"))?;
+        }
+
+        if multiline {
+            if expr || fuzzy || max_distance.is_some() || replace.is_some() {
+                return Err("--multiline only works with plain queries, and not with --replace".into());
+            }
+            queries = queries.iter().map(|query| unescape(query)).collect();
+        }
+
+        if fuzzy && max_distance.is_some() {
+            return Err("--fuzzy and --max-distance can't be used together".into());
+        }
+        // Fuzzy results come out best first, not in file order, so there's nothing to put around them
+        if fuzzy && (before_context.is_some() || after_context.is_some() || context.is_some() || replace.is_some()) {
+            return Err("--fuzzy can't be used with context or --replace".into());
+        }
+        // The index only narrows the files down for the query we start with, not the ones typed
+        // into the prompt
+        if interactive && (json || replace.is_some() || index) {
+            return Err("--tui can't be used with --json, --replace or --index".into());
+        }
+        // The prompt holds one query to edit
+        if interactive && queries.len() > 1 {
+            return Err("--tui takes a single query".into());
+        }
+        if (report || histogram.is_some() || top.is_some()) && (interactive || replace.is_some() || follow || watch) {
+            return Err("--stats, --histogram and --top can't be used with --tui, --replace, --follow or --watch".into());
+        }
+        // Following and watching go on until they're interrupted, and only make sense for plain output
+        if (follow || watch) && (interactive || json || replace.is_some() || index) {
+            return Err("--follow and --watch can't be used with --tui, --json, --replace or --index".into());
+        }
+        if follow && (watch || fuzzy || multiline || encoding.is_some_and(|encoding| encoding != Encoding::Utf8)) {
+            return Err("--follow can't be used with --watch, --fuzzy, --multiline or --encoding".into());
+        }
+        // A compressed file is rewritten whole rather than added to, so there are no new lines to follow
+        if follow && (search_zip || file_paths.iter().any(|path| decompress::Format::from_extension(path).is_some())) {
+            return Err("--follow can't be used with --search-zip or compressed files".into());
+        }
+        if (follow || watch) && file_paths.iter().any(|path| path == "-") {
+            return Err("--follow and --watch need files, not stdin".into());
+        }
+
+        // The directories we were given become the files in them that could match
+        if index {
+            // Approximate and fuzzy matches don't need the query's text to be there, so nothing's ruled out
+            let narrow = !fuzzy && max_distance.is_none();
+            file_paths = index::candidates(&file_paths, &queries, expr, narrow)?;
+        }
+
+        // `--type` keeps only files of those types, and the config file's ignore patterns drop files.
+        // Stdin is searched whatever it is.
+        // `--watch` does this each time it searches, once directories have been turned into files.
+        let mut globs = Vec::new();
+        for name in types {
+            globs.extend(file.globs(name).ok_or_else(|| format!("Unknown file type {name:?}"))?);
+        }
+        if !watch {
+            file_paths.retain(|path| path == "-" || wanted(&globs, &file.ignore, path));
+        }
 
-        let query = args[1].clone();
-        let file_path = args[2].clone();
-        
         let ignore_case = env::var("IGNORE_CASE").is_ok();
-        
-        Ok(Config { query, file_path, ignore_case })
+
+        // Like grep, an explicit -A or -B wins over -C regardless of order
+        let before_context = before_context.or(context).unwrap_or(0);
+        let after_context = after_context.or(context).unwrap_or(0);
+
+        // One worker per core unless told otherwise
+        let threads = threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+
+        let color = match color {
+            "always" => Some(true),
+            "never" => Some(false),
+            "auto" => None,
+            _ => return Err("--color must be one of auto, always or never".into()),
+        };
+        // https://no-color.org: any non-empty NO_COLOR turns off color, unless the command line
+        // asks for it. Asking in the config file isn't enough, since the environment beats that.
+        let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let color = match color {
+            Some(color) if color_on_command_line || !no_color => color,
+            _ => !no_color && io::stdout().is_terminal(),
+        };
+
+        Ok(Config {
+            queries,
+            file_paths,
+            ignore_case,
+            before_context,
+            after_context,
+            threads,
+            sort,
+            line_numbers,
+            color,
+            json,
+            replace,
+            in_place,
+            diff,
+            max_distance,
+            fuzzy,
+            search_zip,
+            expr,
+            multiline,
+            interactive,
+            index,
+            follow,
+            watch,
+            type_globs: globs,
+            ignore: file.ignore.clone(),
+            encoding,
+            // Asking for any part of a report asks for the report
+            report: report || histogram.is_some() || top.is_some(),
+            histogram,
+            top,
+        })
+    }
+}
+
+// Whether `path` is one of the `--type` files (if any were asked for) and isn't ignored
+fn wanted(type_globs: &[String], ignore: &[String], path: &str) -> bool {
+    (type_globs.is_empty() || type_globs.iter().any(|glob| glob::matches(glob, path)))
+        && !ignore.iter().any(|glob| glob::matches(glob, path))
+}
+
+// `\n`, `\t` and `\\` in a multiline query, since line breaks are awkward to type in a shell
+fn unescape(query: &str) -> String {
+    let mut unescaped = String::with_capacity(query.len());
+    let mut chars = query.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('\\') => unescaped.push('\\'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+fn parse_context(value: Option<&String>) -> Result<usize, &'static str> {
+    match value {
+        Some(value) => value.parse().map_err(|_| "Context length must be a non-negative number"),
+        None => Err("Missing context length"),
+    }
+}
+
+fn parse_distance(value: Option<&String>) -> Result<usize, &'static str> {
+    match value {
+        Some(value) => value.parse().map_err(|_| "Edit distance must be a non-negative number"),
+        None => Err("Missing edit distance"),
     }
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.file_path)?;
-    
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
+fn parse_encoding(value: Option<&String>) -> Result<Option<Encoding>, &'static str> {
+    match value.map(String::as_str) {
+        Some("auto") => Ok(None),
+        Some(name) => Encoding::from_name(name)
+            .map(Some)
+            .ok_or("Encoding must be one of auto, utf-8, utf-16le, utf-16be, latin1 or windows-1252"),
+        None => Err("Missing encoding"),
+    }
+}
+
+fn parse_top(value: Option<&String>) -> Result<usize, &'static str> {
+    match value.map(|value| value.parse()) {
+        Some(Ok(count)) if count > 0 => Ok(count),
+        Some(_) => Err("--top needs a positive number"),
+        None => Err("Missing count after --top"),
+    }
+}
+
+fn parse_threads(value: Option<&String>) -> Result<usize, &'static str> {
+    match value.map(|value| value.parse()) {
+        Some(Ok(threads)) if threads > 0 => Ok(threads),
+        Some(_) => Err("Thread count must be a positive number"),
+        None => Err("Missing thread count"),
+    }
+}
+
+// Says whether anything matched, which `main` turns into the exit status like grep does.
+// The modes that run until they're stopped count as having matched.
+pub fn run(config: Config) -> Result<bool, Box<dyn Error>> {
+    if config.interactive {
+        tui::run(&config)?;
+        return Ok(true);
+    }
+
+    if config.watch {
+        return follow::watch(config).map(|_| true);
+    }
+
+    let matcher = matcher_for(&config, &config.queries)?;
+    if config.report {
+        return report::run(&config, &matcher);
+    }
+    if config.follow {
+        follow::follow(&config, &matcher)?;
+        return Ok(true);
+    }
+    search_all(&config, &matcher)
+}
+
+// Search every file in `config` once. Says whether anything matched, or if any file couldn't be searched.
+fn search_all(config: &Config, matcher: &Matcher) -> Result<bool, Box<dyn Error>> {
+    let started = Instant::now();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    // A single file goes straight to stdout; only several need the worker pool and buffering
+    let result = if config.file_paths.len() == 1 {
+        // Files found through an index or in a watched directory are named even when there's only
+        // one, since nobody named them
+        let path = &config.file_paths[0];
+        search_path(config, matcher, path, &mut out, config.index || config.watch)
+            .map(|stats| {
+                let mut summary = Summary::default();
+                summary.add(&stats);
+                summary
+            })
+            // Say which file it was, the same as the workers do
+            .map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))
     } else {
-        search(&config.query, &contents)
+        workers::search_files(config, matcher, &mut out)
     };
-    
-    for line in results {
-        println!("{line}");
+
+    let result = result.and_then(|summary| {
+        if config.json {
+            json::summary(&mut out, &summary, started.elapsed())?;
+        }
+        Ok(summary)
+    });
+
+    match result {
+        Ok(summary) if summary.files_failed > 0 => {
+            Err(format!("{} of {} files could not be searched", summary.files_failed, config.file_paths.len()).into())
+        }
+        Ok(summary) => Ok(summary.files_with_matches > 0),
+        // Whoever was reading our output (`head`, say) has hung up, so there's nothing left to do.
+        // They must have been reading something.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(true),
+        Err(e) => Err(e.into()),
     }
-    
-    Ok(())
 }
 
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let mut results = Vec::new();
-    
-    for line in contents.lines() {
-        if line.contains(query) {
-            results.push(line);
+// `minigrep --index-build|--index-update [DIR]`; `args` starts with the flag
+pub fn run_index(args: &[String]) -> Result<(), Box<dyn Error>> {
+    index::command(args)
+}
+
+// The kind of matcher the flags ask for, looking for `queries`
+fn matcher_for<S: AsRef<str>>(config: &Config, queries: &[S]) -> Result<Matcher, Box<dyn Error>> {
+    Ok(if let Some(max_distance) = config.max_distance {
+        Matcher::approximate(queries, max_distance, config.ignore_case)
+    } else if config.fuzzy {
+        Matcher::fuzzy(queries, config.ignore_case)
+    } else if config.expr {
+        Matcher::expression(expr::parse(queries[0].as_ref())?, config.ignore_case)
+    } else {
+        Matcher::new(queries, config.ignore_case)
+    })
+}
+
+// Search one file (or stdin), printing what we find to `out`
+fn search_path<W: Write>(config: &Config, matcher: &Matcher, path: &str, out: W, show_path: bool) -> io::Result<Stats> {
+    let template = match &config.replace {
+        Some(template) => Some(Template::new(template).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?),
+        None => None,
+    };
+    if let Some(template) = &template
+        && (config.in_place || config.diff)
+    {
+        return replace::rewrite(config, matcher, template, path, out);
+    }
+
+    let Input { mut reader, source } = input::open_text(path, config.search_zip, config.encoding)?;
+
+    let mut printer = Printer::new(out, config);
+    // JSON records always say which file they're about
+    if show_path || config.json {
+        printer = printer.with_path(path);
+    }
+
+    // Ranking needs every line scored before the first one is printed, and a multiline match
+    // could cross from one block into the next, so neither of these can stream
+    if config.fuzzy || config.multiline {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let contents = String::from_utf8_lossy(&bytes);
+        let line_starts = source.line_starts(&bytes, &contents, source.start);
+        let block = Block { contents: &contents, line_offset: 0, byte_offset: source.start, line_starts: line_starts.as_deref() };
+
+        if config.fuzzy {
+            for (_, mut m) in matcher.rank(&contents) {
+                block.relocate(&mut m);
+                printer.matched(&m)?;
+            }
+        } else {
+            printer.print(&block, &block.search(|contents| matcher.search_multiline(contents)))?;
         }
+        return printer.finish();
     }
-    
-    results
+
+    input::for_each_block(reader, source, input::BLOCK_SIZE, |block| {
+        let results = block.search(|contents| matcher.search(contents));
+        let Some(template) = &template else {
+            return printer.print(block, &results);
+        };
+
+        // Show the matching lines as they'd look with the replacements made
+        let replaced: Vec<_> = results.iter().map(|m| template.replace(m)).collect();
+        let results: Vec<Match> = results.iter().zip(&replaced)
+            .map(|(m, (line, submatches))| Match {
+                line_number: m.line_number,
+                byte_offset: m.byte_offset,
+                line,
+                submatches: submatches.clone(),
+            })
+            .collect();
+        printer.print(block, &results)
+    })?;
+
+    printer.finish()
+}
+
+// What we found in one file
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub matched_lines: usize,
+    // Every submatch counts, so a line can hold several
+    pub matches: usize,
+}
+
+// Totals over every file we were asked to search
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub files_searched: usize,
+    pub files_with_matches: usize,
+    // Files we couldn't open or read; not counted in `files_searched`
+    pub files_failed: usize,
+    pub stats: Stats,
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
-    let mut results = Vec::new();
-    
-    for line in contents.lines() {
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
+impl Summary {
+    pub fn add(&mut self, stats: &Stats) {
+        self.files_searched += 1;
+        if stats.matched_lines > 0 {
+            self.files_with_matches += 1;
         }
+        self.stats.matched_lines += stats.matched_lines;
+        self.stats.matches += stats.matches;
     }
-    
-    results
+}
+
+// A matching line along with where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    // 1-based, like every other grep
+    pub line_number: usize,
+    // Offset of the start of the line from the start of the contents
+    pub byte_offset: usize,
+    pub line: &'a str,
+    // Where in `line` the query matched, as byte ranges
+    pub submatches: Vec<Range<usize>>,
+}
+
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    Matcher::new(&[query], false).search(contents)
+}
+
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    Matcher::new(&[query], true).search(contents)
+}
+
+// Lines matching any of `queries`: a few are each looked for on their own, more all in one pass
+pub fn search_any<'a>(queries: &[&str], contents: &'a str) -> Vec<Match<'a>> {
+    Matcher::new(queries, false).search(contents)
+}
+
+// Lines with something within `max_distance` edits (inserted, deleted or substituted characters)
+// of `query`, for finding misspellings
+pub fn search_approximate<'a>(query: &str, contents: &'a str, max_distance: usize) -> Vec<Match<'a>> {
+    Matcher::approximate(&[query], max_distance, false).search(contents)
+}
+
+// Lines with the characters of `query` in order, each with its score, best first
+pub fn search_fuzzy<'a>(query: &str, contents: &'a str) -> Vec<(i32, Match<'a>)> {
+    Matcher::fuzzy(&[query], false).rank(contents)
+}
+
+// Like `search`, but `query` can span lines. Each match holds all the lines it touches,
+// joined by their line breaks, and starts at the first of them.
+pub fn search_multiline<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    Matcher::new(&[query], false).search_multiline(contents)
+}
+
+// Same lines as `str::lines`, but also yields the line number and byte offset of each one
+fn lines(contents: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    let mut offset = 0;
+
+    contents.split_inclusive('\n').enumerate().map(move |(index, raw)| {
+        let start = offset;
+        offset += raw.len();
+
+        let line = match raw.strip_suffix('\n') {
+            Some(line) => line.strip_suffix('\r').unwrap_or(line),
+            None => raw,
+        };
+
+        (index + 1, start, line)
+    })
 }
 
 
@@ -69,7 +660,21 @@ pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a st
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+    use std::fs;
+
+    fn lines_of<'a>(results: &[Match<'a>]) -> Vec<&'a str> {
+        results.iter().map(|m| m.line).collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    // A config from `args` alone, whatever's in the config file of whoever runs the tests
+    fn build(flags: &[&str]) -> Result<Config, String> {
+        Config::build_with(&args(flags), &ConfigFile::default())
+    }
+
     #[test]
     fn case_sensitive() {
         let query = "duct";
@@ -79,10 +684,10 @@ safe, fast, productive.
 Pick three.
 Duct tape.";
         assert_eq!(
-            vec!["safe, fast, productive."], 
-            search(query, contents))
+            vec!["safe, fast, productive."],
+            lines_of(&search(query, contents)))
     }
-    
+
     #[test]
     fn case_insensitive() {
         let query = "rUsT";
@@ -93,9 +698,262 @@ Pick three.
 Trust me.";
         assert_eq!(
             vec!["Rust:", "Trust me."],
-            search_case_insensitive(query, contents)
+            lines_of(&search_case_insensitive(query, contents))
         )
     }
-    
-    
-}
\ No newline at end of file
+
+    #[test]
+    fn case_insensitive_positions() {
+        let contents = "Weiß nicht\nWEISS NICHT\n";
+        let results = search_case_insensitive("weiss", contents);
+        assert_eq!(vec![0..5, 0..5], results.iter().flat_map(|m| m.submatches.clone()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn any_of_several() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
+        assert_eq!(
+            vec!["Rust:", "safe, fast, productive.", "Duct tape."],
+            lines_of(&search_any(&["Rust", "fast", "tape"], contents))
+        );
+    }
+
+    #[test]
+    fn match_positions() {
+        let contents = "Rust:\r\nsafe, fast, productive.\nPick three.\n";
+        assert_eq!(
+            vec![Match {
+                line_number: 2,
+                byte_offset: 7,
+                line: "safe, fast, productive.",
+                submatches: vec![Range { start: 6, end: 10 }],
+            }],
+            search("fast", contents)
+        );
+    }
+
+    #[test]
+    fn context_flags() {
+        let config = build(&["minigrep", "-C", "2", "-A", "1", "to", "poem.txt"]).unwrap();
+        assert_eq!((config.queries.as_slice(), config.file_paths.as_slice()), (&["to".to_string()][..], &["poem.txt".to_string()][..]));
+        assert_eq!((config.before_context, config.after_context), (2, 1));
+
+        assert!(build(&["minigrep", "to", "poem.txt", "-B"]).is_err());
+        assert!(build(&["minigrep", "-B", "x", "to", "poem.txt"]).is_err());
+    }
+
+    #[test]
+    fn reads_stdin_without_a_path() {
+        let config = build(&["minigrep", "to"]).unwrap();
+        assert_eq!(vec!["-"], config.file_paths);
+
+        assert!(build(&["minigrep"]).is_err());
+    }
+
+    #[test]
+    fn several_paths_and_threads() {
+        let config = build(&["minigrep", "to", "a.txt", "-j", "3", "b.txt", "--sort"]).unwrap();
+        assert_eq!(vec!["a.txt", "b.txt"], config.file_paths);
+        assert_eq!(3, config.threads);
+        assert!(config.sort);
+
+        assert!(build(&["minigrep", "-j", "0", "to", "a.txt"]).is_err());
+    }
+
+    #[test]
+    fn several_patterns() {
+        let config = build(&["minigrep", "-e", "to", "-e", "you", "poem.txt"]).unwrap();
+        assert_eq!(vec!["to", "you"], config.queries);
+        assert_eq!(vec!["poem.txt"], config.file_paths);
+
+        assert!(build(&["minigrep", "poem.txt", "-e"]).is_err());
+    }
+
+    #[test]
+    fn color_choice() {
+        assert!(build(&["minigrep", "--color=always", "to"]).unwrap().color);
+        assert!(!build(&["minigrep", "--color=never", "to"]).unwrap().color);
+        assert!(build(&["minigrep", "--color=sometimes", "to"]).is_err());
+    }
+
+    #[test]
+    fn replace_flags() {
+        let config = build(&["minigrep", "--replace", "[$0]", "--diff", "to", "poem.txt"]).unwrap();
+        assert_eq!((Some("[$0]"), false, true), (config.replace.as_deref(), config.in_place, config.diff));
+
+        assert!(build(&["minigrep", "--replace", "$1", "to"]).is_err());
+        assert!(build(&["minigrep", "--in-place", "to", "poem.txt"]).is_err());
+        assert!(build(&["minigrep", "--replace", "x", "--in-place", "to"]).is_err());
+    }
+
+    #[test]
+    fn multiline_matches() {
+        let contents = "\
+fn main() {
+    println!(\"hi\");
+}
+fn helper() {
+}
+";
+        assert_eq!(
+            vec![Match { line_number: 4, byte_offset: 34, line: "fn helper() {\n}", submatches: vec![Range { start: 12, end: 15 }] }],
+            search_multiline("{\n}", contents)
+        );
+
+        // Matches that touch the same line print as one
+        assert_eq!(
+            vec![Match { line_number: 3, byte_offset: 32, line: "}\nfn helper() {\n}", submatches: vec![0..4, 14..17] }],
+            Matcher::new(&["}\nfn", "{\n}"], false).search_multiline(contents)
+        );
+
+        let config = build(&["minigrep", "-U", "a\\nb\\tc\\\\d\\x", "-"]).unwrap();
+        assert_eq!(vec!["a\nb\tc\\d\\x"], config.queries);
+    }
+
+    #[test]
+    fn config_file_defaults() {
+        let file = ConfigFile::parse(r#"
+flags = ["--sort", "-n", "-C", "2", "--color=always", "--json"]
+ignore = ["*.min.js"]
+
+[types]
+web = ["*.html", "*.js"]
+"#).unwrap();
+        let build = |flags: &[&str]| Config::build_with(&args(flags), &file);
+
+        let config = build(&["minigrep", "to", "index.html", "app.js", "app.min.js", "lib.rs"]).unwrap();
+        assert!(config.sort && config.line_numbers && config.json);
+        // NO_COLOR beats the config file (the command-line tests check both ways)
+        assert_eq!(env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()), config.color);
+        assert_eq!((2, 2), (config.before_context, config.after_context));
+        assert_eq!(vec!["index.html", "app.js", "lib.rs"], config.file_paths);
+
+        // The command line wins
+        let config = build(&["minigrep", "--color=never", "-A", "0", "--type", "web", "to", "index.html", "lib.rs"]).unwrap();
+        assert!(!config.color);
+        assert_eq!((2, 0), (config.before_context, config.after_context));
+        assert_eq!(vec!["index.html"], config.file_paths);
+        let config = build(&["minigrep", "--no-sort", "--no-line-number", "--no-json", "to"]).unwrap();
+        assert!(!config.sort && !config.line_numbers && !config.json);
+
+        let config = build(&["minigrep", "-t", "rust", "to", "index.html", "lib.rs"]).unwrap();
+        assert_eq!(vec!["lib.rs"], config.file_paths);
+        assert!(build(&["minigrep", "-t", "cobol", "to"]).is_err());
+
+        let file = ConfigFile::parse(r#"flags = ["-n", "needle"]"#).unwrap();
+        assert!(Config::build_with(&args(&["minigrep", "to"]), &file).is_err());
+    }
+
+    #[test]
+    fn boolean_expressions() {
+        let contents = "\
+error: connection reset
+error: timeout after 30s
+warning: timeout
+";
+        let config = build(&["minigrep", "--expr", "error AND NOT timeout"]).unwrap();
+        let matcher = Matcher::expression(expr::parse(&config.queries[0]).unwrap(), false);
+        assert_eq!(vec!["error: connection reset"], lines_of(&matcher.search(contents)));
+
+        let matcher = Matcher::expression(expr::parse("(reset OR warning) timeout").unwrap(), false);
+        assert_eq!(vec![Range { start: 0, end: 7 }, 9..16], matcher.search(contents)[0].submatches);
+
+        assert_eq!(
+            Err("Bad query expression at column 10: expected a term".to_string()),
+            build(&["minigrep", "--expr", "error AND"]).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn approximate_matches() {
+        let contents = "\
+connection reset by peer
+conection refused
+disconnected
+";
+        assert_eq!(vec!["connection reset by peer"], lines_of(&search_approximate("connection", contents, 0)));
+        assert_eq!(
+            vec!["connection reset by peer", "conection refused"],
+            lines_of(&search_approximate("connection", contents, 2))
+        );
+        assert_eq!(3, search_approximate("connection", contents, 3).len());
+        assert_eq!(vec![Range { start: 0, end: 9 }], search_approximate("connection", contents, 1)[1].submatches);
+    }
+
+    #[test]
+    fn fuzzy_ranking() {
+        let contents = "\
+let buffer = read_file();
+fn read_config_file(path: &Path)
+let rcf = 0;
+";
+        let ranked: Vec<(usize, Vec<Range<usize>>)> = search_fuzzy("rcf", contents).into_iter()
+            .map(|(_, m)| (m.line_number, m.submatches))
+            .collect();
+        assert_eq!(
+            vec![(3, vec![Range { start: 4, end: 7 }]), (2, vec![3..4, 8..9, 11..12])],
+            ranked
+        );
+
+        assert!(build(&["minigrep", "--fuzzy", "--max-distance", "1", "rcf"]).is_err());
+        assert!(build(&["minigrep", "--fuzzy", "-C", "1", "rcf"]).is_err());
+    }
+
+    #[test]
+    fn encoded_files() {
+        let path = env::temp_dir().join(format!("minigrep-encoding-{}.txt", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let search = |flags: &[&str]| {
+            let mut argv = vec!["minigrep", "--no-config", "--json"];
+            argv.extend(flags);
+            argv.extend(["café", path.as_str()]);
+            let config = build(&argv).unwrap();
+            let mut out = Vec::new();
+            search_path(&config, &Matcher::new(&config.queries, false), &path, &mut out, false).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        // UTF-16 with a byte order mark needs no flag, and offsets count its two-byte units
+        let utf16: Vec<u8> = [0xff, 0xfe].into_iter()
+            .chain("one\ncafé two\n".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        fs::write(&path, utf16).unwrap();
+        let found = search(&["-B", "1"]);
+        assert!(found.contains(r#""line_number":1,"byte_offset":2,"line":"one""#), "{found}");
+        assert!(found.contains(r#""line_number":2,"byte_offset":10,"line":"café two""#), "{found}");
+
+        fs::write(&path, b"\xe9\ncaf\xe9\n").unwrap();
+        let found = search(&["--encoding", "latin1"]);
+        fs::remove_file(&path).unwrap();
+        assert!(found.contains(r#""line_number":2,"byte_offset":2,"line":"café""#), "{found}");
+
+        assert!(build(&["minigrep", "--encoding", "ebcdic", "x"]).is_err());
+        assert!(build(&["minigrep", "--encoding", "utf-16le", "--replace", "y", "--diff", "x", "f"]).is_err());
+        assert!(build(&["minigrep", "--index", "--encoding", "latin1", "café"]).is_err());
+    }
+
+    #[test]
+    fn tui_takes_one_query() {
+        assert!(build(&["minigrep", "--tui", "-e", "one", "-e", "two"]).is_err());
+        assert!(build(&["minigrep", "--tui", "-e", "one"]).is_ok());
+        assert!(build(&["minigrep", "--tui", "--index", "one"]).is_err());
+    }
+
+    #[test]
+    fn follow_needs_plain_files() {
+        assert!(build(&["minigrep", "--follow", "error", "app.log"]).is_ok());
+        assert!(build(&["minigrep", "--follow", "-z", "error", "app.log"]).is_err());
+        assert!(build(&["minigrep", "--follow", "error", "app.log", "app.log.1.gz"]).is_err());
+    }
+
+    #[test]
+    fn output_flags() {
+        assert!(build(&["minigrep", "--json", "to"]).unwrap().json);
+        assert!(!build(&["minigrep", "to"]).unwrap().json);
+        assert!(build(&["minigrep", "-z", "to"]).unwrap().search_zip);
+    }
+}
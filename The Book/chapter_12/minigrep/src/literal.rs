@@ -0,0 +1,341 @@
+use std::cmp::Reverse;
+use std::ops::Range;
+
+// Byte-at-a-time loops are the slow part of scanning a big buffer, so `memchr` and `memrchr` look at
+// eight bytes at once by treating them as a u64 ("SWAR", SIMD within a register).
+const ONES: u64 = 0x0101_0101_0101_0101;
+const HIGH_BITS: u64 = 0x8080_8080_8080_8080;
+
+// Sets the high bit of every byte in `word` that equals `byte`, and nothing else
+fn equal_bytes(word: u64, byte: u8) -> u64 {
+    let word = word ^ (ONES * byte as u64);
+    // Only a zero byte keeps its high bit clear through both the add and the or
+    !(((word & !HIGH_BITS) + !HIGH_BITS) | word) & HIGH_BITS
+}
+
+fn read_word(chunk: &[u8]) -> u64 {
+    u64::from_le_bytes(chunk.try_into().unwrap())
+}
+
+// First position of `byte` in `haystack`
+pub fn memchr(byte: u8, haystack: &[u8]) -> Option<usize> {
+    let chunks = haystack.chunks_exact(8);
+    let tail = chunks.remainder();
+
+    for (i, chunk) in chunks.enumerate() {
+        let found = equal_bytes(read_word(chunk), byte);
+        if found != 0 {
+            return Some(i * 8 + found.trailing_zeros() as usize / 8);
+        }
+    }
+
+    let start = haystack.len() - tail.len();
+    tail.iter().position(|&b| b == byte).map(|i| start + i)
+}
+
+// Last position of `byte` in `haystack`
+pub fn memrchr(byte: u8, haystack: &[u8]) -> Option<usize> {
+    let chunks = haystack.rchunks_exact(8);
+    let head = chunks.remainder();
+
+    for (i, chunk) in chunks.enumerate() {
+        let found = equal_bytes(read_word(chunk), byte);
+        if found != 0 {
+            let chunk_start = haystack.len() - (i + 1) * 8;
+            return Some(chunk_start + 7 - found.leading_zeros() as usize / 8);
+        }
+    }
+
+    head.iter().rposition(|&b| b == byte)
+}
+
+// How many times `byte` appears in `haystack`.
+// Counting into a u8 (which can't overflow in 255 bytes) lets the compiler vectorize the inner loop,
+// which it won't do for a plain `filter().count()`.
+pub fn count(byte: u8, haystack: &[u8]) -> usize {
+    haystack.chunks(255)
+        .map(|chunk| chunk.iter().fold(0u8, |n, &b| n + (b == byte) as u8) as usize)
+        .sum()
+}
+
+// Finds one query in a big buffer. Rather than trying every position, it checks a whole stride
+// of them at once for the query's first and last bytes both being in the right place, and only
+// compares the whole query where they are. That pair of bytes rules out almost everything in ordinary text.
+pub struct Finder {
+    needle: Vec<u8>,
+}
+
+// Sixteen positions at a time with SSE2, which every x86_64 has; eight with u64 arithmetic elsewhere
+#[cfg(target_arch = "x86_64")]
+const STRIDE: usize = 16;
+#[cfg(not(target_arch = "x86_64"))]
+const STRIDE: usize = 8;
+
+// How many bits of the `candidates` mask belong to each position
+#[cfg(target_arch = "x86_64")]
+const BITS_PER_POSITION: u32 = 1;
+#[cfg(not(target_arch = "x86_64"))]
+const BITS_PER_POSITION: u32 = 8;
+
+// A mask with bits set for each position in `starts` holding `first` where `ends` also holds `last`.
+// Both slices are STRIDE bytes long.
+#[cfg(target_arch = "x86_64")]
+fn candidates(starts: &[u8], ends: &[u8], first: u8, last: u8) -> u64 {
+    use std::arch::x86_64::{_mm_and_si128, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8};
+
+    assert!(starts.len() == STRIDE && ends.len() == STRIDE);
+    // SAFETY: SSE2 is always available on x86_64, and both slices are 16 bytes,
+    // exactly what an unaligned 128-bit load reads
+    unsafe {
+        let starts = _mm_loadu_si128(starts.as_ptr().cast());
+        let ends = _mm_loadu_si128(ends.as_ptr().cast());
+
+        let first = _mm_cmpeq_epi8(starts, _mm_set1_epi8(first as i8));
+        let last = _mm_cmpeq_epi8(ends, _mm_set1_epi8(last as i8));
+        _mm_movemask_epi8(_mm_and_si128(first, last)) as u32 as u64
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn candidates(starts: &[u8], ends: &[u8], first: u8, last: u8) -> u64 {
+    equal_bytes(read_word(starts), first) & equal_bytes(read_word(ends), last)
+}
+
+impl Finder {
+    pub fn new(needle: &str) -> Finder {
+        Finder { needle: needle.as_bytes().to_vec() }
+    }
+
+    // Where the needle next occurs in `haystack` at or after `from`
+    pub fn find(&self, haystack: &[u8], from: usize) -> Option<usize> {
+        let needle = &self.needle[..];
+        let m = needle.len();
+        match m {
+            0 => return Some(from),
+            1 => return memchr(needle[0], haystack.get(from..)?).map(|i| from + i),
+            _ => {}
+        }
+
+        let (first, last) = (needle[0], needle[m - 1]);
+        let mut i = from;
+
+        // While there's room to read a whole stride at both ends of the needle
+        while i + m - 1 + STRIDE <= haystack.len() {
+            let starts = &haystack[i..i + STRIDE];
+            let ends = &haystack[i + m - 1..i + m - 1 + STRIDE];
+            let mut candidates = candidates(starts, ends, first, last);
+
+            while candidates != 0 {
+                let start = i + (candidates.trailing_zeros() / BITS_PER_POSITION) as usize;
+                if &haystack[start..start + m] == needle {
+                    return Some(start);
+                }
+                // Clear the lowest candidate and try the next
+                candidates &= candidates - 1;
+            }
+
+            i += STRIDE;
+        }
+
+        // Whatever's left is too short for a whole stride
+        while i + m <= haystack.len() {
+            if &haystack[i..i + m] == needle {
+                return Some(i);
+            }
+            i += 1;
+        }
+
+        None
+    }
+
+    pub fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        let mut found = Vec::new();
+        let mut from = 0;
+
+        while let Some(start) = self.find(line.as_bytes(), from) {
+            found.push(start..start + self.needle.len());
+            from = start + self.needle.len();
+        }
+
+        found
+    }
+}
+
+// Aho-Corasick: every query goes into one automaton, so a single pass over the input finds
+// all of them at once, however many there are.
+pub struct AhoCorasick {
+    // A full DFA. Once built, each entry is the next state's row (state * 256) with MATCHED set
+    // if some query ends there, so the hot loop is a single lookup per byte.
+    transitions: Vec<u32>,
+    // Lengths of the queries that end at each state, including ones reached through failure links
+    outputs: Vec<Vec<usize>>,
+}
+
+const NO_STATE: u32 = u32::MAX;
+const MATCHED: u32 = 1 << 31;
+
+impl AhoCorasick {
+    // Empty queries are left out; they'd match everywhere and say nothing
+    pub fn new<S: AsRef<str>>(queries: &[S]) -> AhoCorasick {
+        let mut transitions = vec![NO_STATE; 256];
+        let mut outputs = vec![Vec::new()];
+
+        // Start with a plain trie of the queries
+        for query in queries {
+            let query = query.as_ref().as_bytes();
+            if query.is_empty() {
+                continue;
+            }
+
+            let mut state = 0;
+            for &b in query {
+                let next = transitions[state * 256 + b as usize];
+                state = if next == NO_STATE {
+                    let new_state = outputs.len();
+                    transitions[state * 256 + b as usize] = new_state as u32;
+                    transitions.extend([NO_STATE; 256]);
+                    outputs.push(Vec::new());
+                    new_state
+                } else {
+                    next as usize
+                };
+            }
+            outputs[state].push(query.len());
+        }
+
+        // Then fill in every missing edge breadth first, following failure links,
+        // so searching never has to backtrack
+        let mut failure = vec![0; outputs.len()];
+        let mut queue = std::collections::VecDeque::new();
+
+        for next in transitions.iter_mut().take(256) {
+            match *next {
+                NO_STATE => *next = 0,
+                child => queue.push_back(child as usize),
+            }
+        }
+
+        while let Some(state) = queue.pop_front() {
+            for b in 0..256 {
+                let fallback = transitions[failure[state] * 256 + b];
+                match transitions[state * 256 + b] {
+                    NO_STATE => transitions[state * 256 + b] = fallback,
+                    child => {
+                        let child = child as usize;
+                        failure[child] = fallback as usize;
+                        let inherited = outputs[fallback as usize].clone();
+                        outputs[child].extend(inherited);
+                        queue.push_back(child);
+                    }
+                }
+            }
+        }
+
+        for next in &mut transitions {
+            let state = *next as usize;
+            *next = (state * 256) as u32;
+            if !outputs[state].is_empty() {
+                *next |= MATCHED;
+            }
+        }
+
+        AhoCorasick { transitions, outputs }
+    }
+
+    // Where some query ends next, at or after `from`
+    pub fn find_end(&self, haystack: &[u8], from: usize) -> Option<usize> {
+        let mut row = 0;
+        for (i, &b) in haystack.get(from..)?.iter().enumerate() {
+            let next = self.transitions[row + b as usize];
+            if next & MATCHED != 0 {
+                return Some(from + i + 1);
+            }
+            row = next as usize;
+        }
+        None
+    }
+
+    // Every place any query matches, preferring the leftmost and then the longest where they overlap
+    pub fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        let mut candidates = Vec::new();
+        let mut row = 0;
+        for (i, &b) in line.as_bytes().iter().enumerate() {
+            let next = self.transitions[row + b as usize];
+            row = (next & !MATCHED) as usize;
+            if next & MATCHED != 0 {
+                for len in &self.outputs[row / 256] {
+                    candidates.push(i + 1 - len..i + 1);
+                }
+            }
+        }
+
+        candidates.sort_by_key(|range| (range.start, Reverse(range.end)));
+
+        let mut found: Vec<Range<usize>> = Vec::new();
+        for candidate in candidates {
+            if found.last().is_none_or(|last| candidate.start >= last.end) {
+                found.push(candidate);
+            }
+        }
+        found
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_at_a_time_helpers() {
+        let haystack = b"one\ntwo\nthree\nfour\nfive\n\xffsix";
+        let expected_first = haystack.iter().position(|&b| b == b'\n');
+        let expected_last = haystack.iter().rposition(|&b| b == b'\n');
+
+        assert_eq!(expected_first, memchr(b'\n', haystack));
+        assert_eq!(expected_last, memrchr(b'\n', haystack));
+        assert_eq!(5, count(b'\n', haystack));
+        assert_eq!(Some(24), memchr(0xff, haystack));
+        assert_eq!(None, memchr(b'z', haystack));
+        assert_eq!(None, memrchr(b'z', haystack));
+
+        // Every offset into the chunks, and the leftover bytes on either side
+        for i in 0..haystack.len() {
+            let b = haystack[i];
+            assert_eq!(haystack.iter().position(|&x| x == b), memchr(b, haystack));
+            assert_eq!(haystack.iter().rposition(|&x| x == b), memrchr(b, haystack));
+            assert_eq!(haystack.iter().filter(|&&x| x == b).count(), count(b, haystack));
+        }
+    }
+
+    #[test]
+    fn finder_finds_every_occurrence() {
+        let finder = Finder::new("abab");
+        assert_eq!(vec![2..6, 6..10], finder.find_all("xxabababab"));
+        assert_eq!(None, finder.find(b"aba", 0));
+        assert_eq!(Some(0), Finder::new("x").find(b"x", 0));
+
+        // Long enough to go through the word-at-a-time loop, with hits on either side of a word boundary
+        let haystack = "the quick brown fox jumps over the lazy dog, the quick brown fox";
+        let expected: Vec<_> = haystack.match_indices("the").map(|(i, _)| i..i + 3).collect();
+        assert_eq!(expected, Finder::new("the").find_all(haystack));
+        assert_eq!(Some(45), Finder::new("the quick").find(haystack.as_bytes(), 1));
+    }
+
+    #[test]
+    fn aho_corasick_prefers_leftmost_longest() {
+        let finder = AhoCorasick::new(&["he", "she", "hers", "his"]);
+        assert_eq!(vec![1..4, 4..8], finder.find_all("ushehers"));
+        assert_eq!(Some(4), finder.find_end(b"ushers", 0));
+        assert_eq!(None, finder.find_end(b"nothing", 0));
+    }
+
+    #[test]
+    fn aho_corasick_skips_empty_queries() {
+        let finder = AhoCorasick::new(&["", "b"]);
+        assert_eq!(vec![1..2], finder.find_all("abc"));
+    }
+}
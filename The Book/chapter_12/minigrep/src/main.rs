@@ -2,25 +2,39 @@ use std::process;
 use std::env;
 
 
+// Exit statuses, the same as grep's
+const MATCHED: i32 = 0;
+const NO_MATCH: i32 = 1;
+const ERROR: i32 = 2;
+
 fn main() {
     // Get args from user input
     let args = env::args().collect::<Vec<String>>();
     
+    // `minigrep --index-build|--index-update [DIR]` manages an index rather than searching
+    if let [_, action, ..] = args.as_slice()
+        && (action == "--index-build" || action == "--index-update")
+    {
+        if let Err(e) = minigrep::run_index(&args[1..]) {
+            eprintln!("Application error: {e}");
+            process::exit(ERROR);
+        }
+        return;
+    }
+
     // Create config
     let config = minigrep::Config::build(&args).unwrap_or_else(|err| {
         eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
+        process::exit(ERROR);
     });
     
     // Run program using config from args
-    if let Err(e) = minigrep::run(config) {
-        eprintln!("Application error: {e}");
-        process::exit(1);
+    match minigrep::run(config) {
+        Ok(true) => process::exit(MATCHED),
+        Ok(false) => process::exit(NO_MATCH),
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            process::exit(ERROR);
+        }
     }
 }
-
-
-
-
-
-
@@ -0,0 +1,347 @@
+use std::cmp::Reverse;
+use std::ops::Range;
+
+use crate::expr::{Expr, Query};
+use crate::fold::FoldedQuery;
+use crate::fuzzy::{Approximate, Subsequence};
+use crate::literal::{self, AhoCorasick, Finder};
+use crate::{lines, Match};
+
+// Up to this many queries, looking for each one with its own `Finder` beats one pass with
+// Aho-Corasick: the finders skip through the text a stride at a time, while the automaton has to
+// step through every byte. Past it, the finders' passes add up to more than that one (on the
+// benchmark's log, each finder costs about a fourteenth of the automaton).
+const MAX_FINDERS: usize = 12;
+
+// Everything we know how to search for, picked once up front from the queries and flags
+pub enum Matcher {
+    // An empty query matches every line
+    Everything,
+    Literal(Finder),
+    // A few queries (`-e one -e two`), each looked for on its own; a line matches if any of them do
+    Literals(Vec<Finder>),
+    // Too many queries for that, all looked for at once
+    Any(AhoCorasick),
+    // Case-insensitive, one or more queries
+    Folded(Vec<FoldedQuery>),
+    // Within some number of edits of any of the queries (`--max-distance`)
+    Approximate(Vec<Approximate>),
+    // Has the characters of any of the queries in order (`--fuzzy`)
+    Fuzzy(Vec<Subsequence>),
+    // A boolean query (`--expr`), with a matcher for each of its terms
+    Expression {
+        expr: Expr,
+        terms: Vec<Matcher>,
+        // Which terms to highlight; see `Expr::positive_terms`
+        positive: Vec<bool>,
+    },
+}
+
+impl Matcher {
+    pub fn new<S: AsRef<str>>(queries: &[S], ignore_case: bool) -> Matcher {
+        if queries.iter().any(|query| query.as_ref().is_empty()) {
+            return Matcher::Everything;
+        }
+
+        if ignore_case {
+            Matcher::Folded(queries.iter().map(|query| FoldedQuery::new(query.as_ref())).collect())
+        } else if let [query] = queries {
+            Matcher::Literal(Finder::new(query.as_ref()))
+        } else if queries.len() <= MAX_FINDERS {
+            Matcher::Literals(queries.iter().map(|query| Finder::new(query.as_ref())).collect())
+        } else {
+            Matcher::Any(AhoCorasick::new(queries))
+        }
+    }
+
+    pub fn approximate<S: AsRef<str>>(queries: &[S], max_distance: usize, ignore_case: bool) -> Matcher {
+        if queries.iter().any(|query| query.as_ref().is_empty()) {
+            return Matcher::Everything;
+        }
+
+        Matcher::Approximate(queries.iter().map(|query| Approximate::new(query.as_ref(), max_distance, ignore_case)).collect())
+    }
+
+    // Like fuzzy finders, a query with no capitals ignores case even without IGNORE_CASE
+    pub fn fuzzy<S: AsRef<str>>(queries: &[S], ignore_case: bool) -> Matcher {
+        if queries.iter().any(|query| query.as_ref().is_empty()) {
+            return Matcher::Everything;
+        }
+
+        Matcher::Fuzzy(queries.iter()
+            .map(|query| {
+                let query = query.as_ref();
+                Subsequence::new(query, ignore_case || !query.chars().any(char::is_uppercase))
+            })
+            .collect())
+    }
+
+    pub fn expression(query: Query, ignore_case: bool) -> Matcher {
+        let mut positive = vec![false; query.terms.len()];
+        query.expr.positive_terms(&mut positive);
+        let terms = query.terms.iter().map(|term| Matcher::new(&[term], ignore_case)).collect();
+        Matcher::Expression { expr: query.expr, terms, positive }
+    }
+
+    // Byte ranges in `line` where we matched
+    pub fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        match self {
+            Matcher::Everything => Vec::new(),
+            Matcher::Literal(finder) => finder.find_all(line),
+            Matcher::Literals(finders) => merge(finders.iter().map(|finder| finder.find_all(line)).collect()),
+            Matcher::Any(finder) => finder.find_all(line),
+            Matcher::Folded(queries) => merge(queries.iter().map(|query| query.find_all(line)).collect()),
+            Matcher::Approximate(queries) => merge(queries.iter().map(|query| query.find_all(line)).collect()),
+            Matcher::Fuzzy(_) => self.score(line).map(|(_, found)| found).unwrap_or_default(),
+            Matcher::Expression { .. } => self.evaluate(line).unwrap_or_default(),
+        }
+    }
+
+    // For `Expression`: whether `line` satisfies it, and if so, where its terms are
+    fn evaluate(&self, line: &str) -> Option<Vec<Range<usize>>> {
+        let Matcher::Expression { expr, terms, positive } = self else {
+            return None;
+        };
+
+        let found: Vec<Vec<Range<usize>>> = terms.iter().map(|term| term.find_all(line)).collect();
+        let present: Vec<bool> = found.iter().map(|ranges| !ranges.is_empty()).collect();
+        if !expr.eval(&present) {
+            return None;
+        }
+
+        Some(merge(found.into_iter().zip(positive).filter(|(_, positive)| **positive).map(|(found, _)| found).collect()))
+    }
+
+    // How good a fuzzy match `line` is, going by its best query, and which characters matched.
+    // Every other kind of match just matches, so it scores zero.
+    pub fn score(&self, line: &str) -> Option<(i32, Vec<Range<usize>>)> {
+        match self {
+            Matcher::Fuzzy(queries) => queries.iter().filter_map(|query| query.score(line)).max_by_key(|(score, _)| *score),
+            Matcher::Expression { .. } => self.evaluate(line).map(|found| (0, found)),
+            _ => {
+                let found = self.find_all(line);
+                (matches!(self, Matcher::Everything) || !found.is_empty()).then_some((0, found))
+            }
+        }
+    }
+
+    pub fn search<'a>(&self, contents: &'a str) -> Vec<Match<'a>> {
+        match self {
+            Matcher::Everything => search_lines(contents, |_| Some(Vec::new())),
+            Matcher::Literal(finder) => search_hits(contents, |from| finder.find(contents.as_bytes(), from), self),
+            Matcher::Literals(finders) => {
+                // Where each query occurs next, so none of them has to look past its own next hit
+                // more than once
+                let bytes = contents.as_bytes();
+                let mut next: Vec<Option<usize>> = finders.iter().map(|finder| finder.find(bytes, 0)).collect();
+                search_hits(contents, |from| {
+                    for (finder, hit) in finders.iter().zip(&mut next) {
+                        if hit.is_some_and(|hit| hit < from) {
+                            *hit = finder.find(bytes, from);
+                        }
+                    }
+                    next.iter().flatten().min().copied()
+                }, self)
+            }
+            Matcher::Any(finder) => search_hits(contents, |from| finder.find_end(contents.as_bytes(), from), self),
+            Matcher::Folded(_) => search_lines(contents, |line| {
+                let found = self.find_all(line);
+                (!found.is_empty()).then_some(found)
+            }),
+            // Bitap is quick to rule lines out; only the ones left need their matches found
+            Matcher::Approximate(queries) => search_lines(contents, |line| {
+                queries.iter().any(|query| query.is_match(line)).then(|| self.find_all(line))
+            }),
+            Matcher::Fuzzy(_) => search_lines(contents, |line| self.score(line).map(|(_, found)| found)),
+            Matcher::Expression { .. } => search_lines(contents, |line| self.evaluate(line)),
+        }
+    }
+
+    // Like `search`, but matches can run across line breaks (`--multiline`). Each match covers
+    // every line a submatch touches, and submatches that share a line end up in the same match.
+    // Only makes sense for the literal kinds of matcher; the others work a line at a time.
+    pub fn search_multiline<'a>(&self, contents: &'a str) -> Vec<Match<'a>> {
+        if let Matcher::Everything = self {
+            return self.search(contents);
+        }
+
+        let bytes = contents.as_bytes();
+        let mut results: Vec<Match> = Vec::new();
+        // Where the last match's lines end, and the line number there
+        let mut span_end = 0;
+        let mut line_number = 1;
+        let mut counted_to = 0;
+
+        for range in self.find_all(contents) {
+            let line_start = literal::memrchr(b'\n', &bytes[..range.start]).map_or(0, |i| i + 1);
+            // The line the match's last byte is on, which is the line it ends with a break
+            let last = range.end.max(range.start + 1) - 1;
+            let line_end = literal::memchr(b'\n', &bytes[last.min(bytes.len())..]).map_or(bytes.len(), |i| last + i);
+
+            match results.last_mut() {
+                // Shares a line with the previous match, so they print as one
+                Some(m) if line_start <= span_end => {
+                    let m_end = m.byte_offset + m.line.len();
+                    if line_end > m_end {
+                        m.line = trim_line_end(&contents[m.byte_offset..line_end]);
+                    }
+                    m.submatches.push(range.start - m.byte_offset..(range.end - m.byte_offset).min(m.line.len()));
+                }
+                _ => {
+                    line_number += literal::count(b'\n', &bytes[counted_to..line_start]);
+                    counted_to = line_start;
+
+                    let line = trim_line_end(&contents[line_start..line_end]);
+                    let submatches = vec![Range { start: range.start - line_start, end: (range.end - line_start).min(line.len()) }];
+                    results.push(Match { line_number, byte_offset: line_start, line, submatches });
+                }
+            }
+            span_end = line_end;
+        }
+
+        results
+    }
+
+    // Every matching line with its score, best first (and in order among equals)
+    pub fn rank<'a>(&self, contents: &'a str) -> Vec<(i32, Match<'a>)> {
+        let mut ranked: Vec<(i32, Match)> = lines(contents)
+            .filter_map(|(line_number, byte_offset, line)| {
+                let (score, submatches) = self.score(line)?;
+                Some((score, Match { line_number, byte_offset, line, submatches }))
+            })
+            .collect();
+        ranked.sort_by_key(|(score, _)| Reverse(*score));
+        ranked
+    }
+}
+
+fn trim_line_end(line: &str) -> &str {
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+// Submatches from several queries, in order and without overlaps
+fn merge(found: Vec<Vec<Range<usize>>>) -> Vec<Range<usize>> {
+    if found.len() <= 1 {
+        return found.into_iter().next().unwrap_or_default();
+    }
+
+    // Leftmost first, and the longest of those that start together, the same as Aho-Corasick
+    let mut found: Vec<Range<usize>> = found.into_iter().flatten().collect();
+    found.sort_by_key(|range| (range.start, Reverse(range.end)));
+    found.dedup_by(|later, earlier| later.start < earlier.end);
+    found
+}
+
+// Check every line on its own. `is_match` gives back the submatches for lines that match.
+fn search_lines<'a, F>(contents: &'a str, is_match: F) -> Vec<Match<'a>>
+where
+    F: Fn(&str) -> Option<Vec<Range<usize>>>,
+{
+    let mut results = Vec::new();
+
+    for (line_number, byte_offset, line) in lines(contents) {
+        if let Some(submatches) = is_match(line) {
+            results.push(Match { line_number, byte_offset, line, submatches });
+        }
+    }
+
+    results
+}
+
+// Search the whole buffer at once, and only go looking for line boundaries around the hits.
+// `next_hit` returns some position inside the next match (or None), searching from the given offset,
+// which is always the start of a line.
+fn search_hits<'a, F>(contents: &'a str, mut next_hit: F, matcher: &Matcher) -> Vec<Match<'a>>
+where
+    F: FnMut(usize) -> Option<usize>,
+{
+    let bytes = contents.as_bytes();
+    let mut results = Vec::new();
+
+    let mut from = 0;
+    let mut line_number = 1;
+    // Newlines before here have already been counted into `line_number`
+    let mut counted_to = 0;
+
+    while let Some(hit) = next_hit(from) {
+        // A hit right at the end of the buffer belongs to the last line
+        let hit = hit.min(bytes.len().saturating_sub(1));
+
+        let line_start = literal::memrchr(b'\n', &bytes[..hit]).map_or(0, |i| i + 1);
+        let line_end = literal::memchr(b'\n', &bytes[hit..]).map_or(bytes.len(), |i| hit + i);
+
+        line_number += literal::count(b'\n', &bytes[counted_to..line_start]);
+        counted_to = line_start;
+
+        let line = &contents[line_start..line_end];
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        // A query containing a line break can hit across two lines, which doesn't count
+        let submatches = matcher.find_all(line);
+        if !submatches.is_empty() {
+            results.push(Match { line_number, byte_offset: line_start, line, submatches });
+        }
+
+        from = line_end + 1;
+        if from > bytes.len() {
+            break;
+        }
+    }
+
+    results
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENTS: &str = "\
+error: disk full
+warning: retrying
+info: all good\r
+error: timeout
+";
+
+    fn found<'a>(matcher: &Matcher, contents: &'a str) -> Vec<(usize, usize, &'a str)> {
+        matcher.search(contents).iter().map(|m| (m.line_number, m.byte_offset, m.line)).collect()
+    }
+
+    #[test]
+    fn whole_buffer_positions_match_line_by_line() {
+        let matcher = Matcher::new(&["o"], false);
+        let expected: Vec<_> = lines(CONTENTS).filter(|(_, _, line)| line.contains('o')).collect();
+        assert_eq!(expected, found(&matcher, CONTENTS));
+    }
+
+    #[test]
+    fn finders_and_automaton_agree() {
+        let queries = ["error", "err", "or: t", "good\r", "\nwarn", "timeout", "l"];
+        let finders = Matcher::new(&queries, false);
+        assert!(matches!(finders, Matcher::Literals(_)));
+        let automaton = Matcher::Any(AhoCorasick::new(&queries));
+        assert_eq!(automaton.search(CONTENTS), finders.search(CONTENTS));
+        assert_eq!(automaton.search_multiline(CONTENTS), finders.search_multiline(CONTENTS));
+
+        let many: Vec<String> = (0..=MAX_FINDERS).map(|i| format!("query {i}")).collect();
+        assert!(matches!(Matcher::new(&many, false), Matcher::Any(_)));
+    }
+
+    #[test]
+    fn several_queries() {
+        let matcher = Matcher::new(&["disk", "good", "timeout"], false);
+        assert_eq!(
+            vec![(1, 0, "error: disk full"), (3, 35, "info: all good"), (4, 51, "error: timeout")],
+            found(&matcher, CONTENTS)
+        );
+    }
+
+    #[test]
+    fn hits_across_lines_do_not_count() {
+        assert!(Matcher::new(&["full\nwarning"], false).search(CONTENTS).is_empty());
+        assert!(Matcher::new(&["good\r"], false).search(CONTENTS).is_empty());
+    }
+}
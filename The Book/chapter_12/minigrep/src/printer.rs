@@ -0,0 +1,351 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::ops::Range;
+
+use crate::{json, Config, Match, Stats};
+use crate::input::Block;
+
+// The same colors GNU grep uses by default
+const FILE_NAME: &str = "\x1b[35m";
+const LINE_NUMBER: &str = "\x1b[32m";
+const SEPARATOR: &str = "\x1b[36m";
+const MATCHED_TEXT: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
+
+// Prints matching lines, plus any context lines around them, as text or as JSON records.
+// Lines are fed in order, so overlapping context windows merge on their own:
+// a line is never printed twice, and `--` only goes between groups that don't touch.
+pub struct Printer<W: Write> {
+    out: W,
+    before: usize,
+    after: usize,
+    // The last few lines we skipped (number, byte offset, text),
+    // in case the next match wants them as before-context
+    history: VecDeque<(usize, usize, String)>,
+    // How many more lines still belong to the previous match's after-context
+    after_remaining: usize,
+    last_printed: Option<usize>,
+    // Set when searching more than one file, so each line says where it came from
+    path: Option<String>,
+    line_numbers: bool,
+    color: bool,
+    json: bool,
+    // Whether we've written this file's JSON begin record yet
+    began: bool,
+    stats: Stats,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(out: W, config: &Config) -> Printer<W> {
+        Printer {
+            out,
+            before: config.before_context,
+            after: config.after_context,
+            history: VecDeque::with_capacity(config.before_context),
+            after_remaining: 0,
+            last_printed: None,
+            path: None,
+            line_numbers: config.line_numbers,
+            color: config.color,
+            json: config.json,
+            began: false,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn with_path(mut self, path: &str) -> Printer<W> {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    // Print every match in `results`, walking the block for context lines when we need them.
+    // Blocks have to be fed in order; history and after-context carry over from one to the next.
+    pub fn print(&mut self, block: &Block, results: &[Match]) -> io::Result<()> {
+        if self.before == 0 && self.after == 0 {
+            for m in results {
+                self.matched(m)?;
+            }
+            return Ok(());
+        }
+
+        let mut results = results.iter().peekable();
+        // Lines still to come that a multiline match has already printed
+        let mut spanned = 0;
+        for (line_number, byte_offset, line) in crate::lines(block.contents) {
+            let byte_offset = block.original_offset(line_number - 1, byte_offset);
+            let line_number = line_number + block.line_offset;
+            if spanned > 0 {
+                spanned -= 1;
+                continue;
+            }
+            match results.next_if(|m| m.line_number == line_number) {
+                Some(m) => {
+                    self.matched(m)?;
+                    spanned = m.line.matches('\n').count();
+                }
+                None => self.context(line_number, byte_offset, line)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // Call once the whole file has been printed, to close it off and find out what we found
+    pub fn finish(mut self) -> io::Result<Stats> {
+        if self.began {
+            let path = self.path.as_deref().unwrap_or("-");
+            json::end(&mut self.out, path, &self.stats)?;
+        }
+        Ok(self.stats)
+    }
+
+    pub fn matched(&mut self, m: &Match) -> io::Result<()> {
+        // Anything still in the history is within `before` lines of this match
+        while let Some((line_number, byte_offset, line)) = self.history.pop_front() {
+            self.write_line(line_number, byte_offset, &line, None)?;
+        }
+
+        if !m.line.contains('\n') {
+            self.write_line(m.line_number, m.byte_offset, m.line, Some(&m.submatches))?;
+            self.stats.matched_lines += 1;
+        } else {
+            // A multiline match (`--multiline`) prints each of its lines, highlighting its part of each one
+            let mut offset = 0;
+            for (i, line) in m.line.split('\n').enumerate() {
+                let text = line.strip_suffix('\r').unwrap_or(line);
+                let submatches: Vec<Range<usize>> = m.submatches.iter()
+                    .map(|range| range.start.max(offset) - offset..range.end.min(offset + text.len()).saturating_sub(offset))
+                    .filter(|range| range.start < range.end)
+                    .collect();
+
+                self.write_line(m.line_number + i, m.byte_offset + offset, text, Some(&submatches))?;
+                self.stats.matched_lines += 1;
+                offset += line.len() + 1;
+            }
+        }
+        self.after_remaining = self.after;
+
+        // A line matched by an empty query has nothing to point at, but it's still one match
+        self.stats.matches += m.submatches.len().max(1);
+
+        Ok(())
+    }
+
+    pub fn context(&mut self, line_number: usize, byte_offset: usize, line: &str) -> io::Result<()> {
+        if self.after_remaining > 0 {
+            self.after_remaining -= 1;
+            return self.write_line(line_number, byte_offset, line, None);
+        }
+
+        if self.before == 0 {
+            return Ok(());
+        }
+
+        // Reuse the oldest entry's allocation once the history is full
+        let mut entry = if self.history.len() == self.before {
+            self.history.pop_front().unwrap().2
+        } else {
+            String::new()
+        };
+        entry.clear();
+        entry.push_str(line);
+        self.history.push_back((line_number, byte_offset, entry));
+
+        Ok(())
+    }
+
+    // `submatches` is None for context lines
+    fn write_line(
+        &mut self,
+        line_number: usize,
+        byte_offset: usize,
+        line: &str,
+        submatches: Option<&[Range<usize>]>,
+    ) -> io::Result<()> {
+        if self.json {
+            let path = self.path.as_deref().unwrap_or("-");
+            if !self.began {
+                json::begin(&mut self.out, path)?;
+                self.began = true;
+            }
+            return json::line(&mut self.out, path, line_number, byte_offset, line, submatches);
+        }
+
+        let has_context = self.before > 0 || self.after > 0;
+        if has_context && self.last_printed.is_some_and(|last| line_number > last + 1) {
+            self.paint(SEPARATOR, "--")?;
+            writeln!(self.out)?;
+        }
+        self.last_printed = Some(line_number);
+
+        // Same as grep: `path:12:line` for matches, `path-12-line` for context
+        let separator = if submatches.is_some() { ':' } else { '-' };
+        if let Some(path) = &self.path {
+            paint(&mut self.out, self.color, FILE_NAME, path)?;
+            self.paint(SEPARATOR, separator)?;
+        }
+        if self.line_numbers {
+            self.paint(LINE_NUMBER, line_number)?;
+            self.paint(SEPARATOR, separator)?;
+        }
+
+        let submatches = match submatches {
+            Some(submatches) if self.color => submatches,
+            _ => return writeln!(self.out, "{line}"),
+        };
+
+        let mut written = 0;
+        for submatch in submatches {
+            // Skip anything overlapping what we've already highlighted
+            if submatch.start < written {
+                continue;
+            }
+            write!(self.out, "{}", &line[written..submatch.start])?;
+            self.paint(MATCHED_TEXT, &line[submatch.clone()])?;
+            written = submatch.end;
+        }
+        writeln!(self.out, "{}", &line[written..])
+    }
+
+    fn paint(&mut self, color: &str, text: impl Display) -> io::Result<()> {
+        paint(&mut self.out, self.color, color, text)
+    }
+}
+
+fn paint<W: Write>(out: &mut W, enabled: bool, color: &str, text: impl Display) -> io::Result<()> {
+    if enabled {
+        write!(out, "{color}{text}{RESET}")
+    } else {
+        write!(out, "{text}")
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search;
+
+    const CONTENTS: &str = "\
+one
+two match
+three
+four
+five
+six match
+seven
+eight match
+nine
+ten";
+
+    fn config(flags: &[&str]) -> Config {
+        let mut args = vec!["minigrep".to_string(), "--no-config".to_string(), "--color=never".to_string()];
+        args.extend(flags.iter().map(|flag| flag.to_string()));
+        args.push("match".to_string());
+        Config::build(&args).unwrap()
+    }
+
+    fn render(before: usize, after: usize) -> String {
+        let mut out = Vec::new();
+        let config = config(&["-B", &before.to_string(), "-A", &after.to_string()]);
+        let mut printer = Printer::new(&mut out, &config);
+        let block = Block { contents: CONTENTS, line_offset: 0, byte_offset: 0, line_starts: None };
+        printer.print(&block, &search("match", CONTENTS)).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn no_context() {
+        assert_eq!("two match\nsix match\neight match\n", render(0, 0));
+    }
+
+    #[test]
+    fn multiline_matches_print_every_line() {
+        let mut out = Vec::new();
+        let config = config(&["-n", "-A", "1"]);
+        let mut printer = Printer::new(&mut out, &config);
+        let block = Block { contents: CONTENTS, line_offset: 0, byte_offset: 0, line_starts: None };
+        printer.print(&block, &crate::search_multiline("match\nseven\neight", CONTENTS)).unwrap();
+
+        assert_eq!(Stats { matched_lines: 3, matches: 1 }, printer.finish().unwrap());
+        assert_eq!("6:six match\n7:seven\n8:eight match\n9-nine\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn separates_groups() {
+        assert_eq!("one\ntwo match\n--\nfive\nsix match\nseven\neight match\n", render(1, 0));
+        assert_eq!("two match\nthree\n--\nsix match\nseven\neight match\nnine\n", render(0, 1));
+    }
+
+    #[test]
+    fn merges_overlapping_windows() {
+        assert_eq!(
+            "one\ntwo match\nthree\nfour\nfive\nsix match\nseven\neight match\nnine\nten\n",
+            render(2, 2)
+        );
+    }
+
+    #[test]
+    fn prefixes_path() {
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, &config(&["-A", "1", "-n"])).with_path("numbers.txt");
+        let block = Block { contents: CONTENTS, line_offset: 0, byte_offset: 0, line_starts: None };
+        printer.print(&block, &search("six", CONTENTS)).unwrap();
+
+        assert_eq!("numbers.txt:6:six match\nnumbers.txt-7-seven\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn highlights_submatches() {
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, &config(&["--color=always"]));
+        let contents = "a match, another match\n";
+        let block = Block { contents, line_offset: 0, byte_offset: 0, line_starts: None };
+        printer.print(&block, &search("match", contents)).unwrap();
+
+        assert_eq!(
+            "a \x1b[1;31mmatch\x1b[0m, another \x1b[1;31mmatch\x1b[0m\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn json_records() {
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, &config(&["--json", "-B", "1"])).with_path("numbers.txt");
+        let block = Block { contents: CONTENTS, line_offset: 0, byte_offset: 0, line_starts: None };
+        printer.print(&block, &search("six", CONTENTS)).unwrap();
+        let stats = printer.finish().unwrap();
+
+        assert_eq!(Stats { matched_lines: 1, matches: 1 }, stats);
+        assert_eq!(
+            concat!(
+                r#"{"type":"begin","path":"numbers.txt"}"#, "\n",
+                r#"{"type":"context","path":"numbers.txt","line_number":5,"byte_offset":25,"line":"five"}"#, "\n",
+                r#"{"type":"match","path":"numbers.txt","line_number":6,"byte_offset":30,"line":"six match","#,
+                r#""submatches":[{"text":"six","start":0,"end":3}]}"#, "\n",
+                r#"{"type":"end","path":"numbers.txt","matched_lines":1,"matches":1}"#, "\n",
+            ),
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn context_carries_across_blocks() {
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, &config(&["-C", "1"]));
+
+        crate::input::for_each_block(CONTENTS.as_bytes(), Default::default(), 1, |block| {
+            printer.print(block, &block.search(|contents| search("match", contents)))
+        }).unwrap();
+
+        assert_eq!(
+            "one\ntwo match\nthree\n--\nfive\nsix match\nseven\neight match\nnine\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
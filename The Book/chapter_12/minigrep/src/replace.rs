@@ -0,0 +1,334 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::process;
+
+use crate::{input, Config, Match, Stats};
+use crate::decompress::Format;
+use crate::matcher::Matcher;
+
+// Lines of unchanged context around each hunk of `--diff` output, same as `diff -u`
+const CONTEXT: usize = 3;
+
+const CAPTURE_GROUPS: &str = "Only $0 (the whole match) can be used in --replace; capture groups need a regex pattern";
+
+// What each match gets replaced with (`--replace`): plain text, where `$0` or `${0}` stands for
+// whatever matched and `$$` is a literal dollar sign
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+enum Part {
+    Text(String),
+    Matched,
+}
+
+impl Template {
+    pub fn new(template: &str) -> Result<Template, &'static str> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+
+        let mut rest = template;
+        while let Some(dollar) = rest.find('$') {
+            text.push_str(&rest[..dollar]);
+            let after = &rest[dollar + 1..];
+
+            if let Some(after) = after.strip_prefix('$') {
+                text.push('$');
+                rest = after;
+                continue;
+            }
+
+            // `${name}`, or `$name` running up to the first character that can't be part of one
+            let (reference, len) = match after.strip_prefix('{') {
+                Some(braced) => braced.find('}').map_or(("", 0), |end| (&braced[..end], end + 2)),
+                None => {
+                    let end = after.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(after.len());
+                    (&after[..end], end)
+                }
+            };
+
+            match reference {
+                // Nothing that looks like a reference, so it's just a dollar sign
+                "" => text.push('$'),
+                "0" => {
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Matched);
+                }
+                _ => return Err(CAPTURE_GROUPS),
+            }
+            rest = &after[len..];
+        }
+
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Template { parts })
+    }
+
+    fn expand(&self, matched: &str, out: &mut String) {
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Matched => out.push_str(matched),
+            }
+        }
+    }
+
+    // The matched line with every submatch replaced, and where the replacements ended up in it
+    pub fn replace(&self, m: &Match) -> (String, Vec<Range<usize>>) {
+        let mut replaced = String::with_capacity(m.line.len());
+        let mut ranges = Vec::with_capacity(m.submatches.len());
+
+        let mut last = 0;
+        for range in &m.submatches {
+            replaced.push_str(&m.line[last..range.start]);
+            let start = replaced.len();
+            self.expand(&m.line[range.clone()], &mut replaced);
+            ranges.push(start..replaced.len());
+            last = range.end;
+        }
+        replaced.push_str(&m.line[last..]);
+
+        (replaced, ranges)
+    }
+}
+
+// Replace every match in one file, and either write the result back (`--in-place`)
+// or print what would change as a unified diff (`--diff`).
+// Unlike searching, this needs the whole file at once, and it has to be valid UTF-8:
+// rewriting a file we could only read lossily would quietly mangle it.
+pub fn rewrite<W: Write>(
+    config: &Config,
+    matcher: &Matcher,
+    template: &Template,
+    path: &str,
+    mut out: W,
+) -> io::Result<Stats> {
+    // We'd write the file back decompressed, which is no good to anyone
+    if config.in_place && Format::from_extension(path).is_some() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't rewrite compressed files in place"));
+    }
+
+    let mut bytes = Vec::new();
+    input::open(path, config.search_zip && !config.in_place)?.read_to_end(&mut bytes)?;
+    let contents = String::from_utf8(bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "not valid UTF-8, leaving it alone"))?;
+
+    let mut stats = Stats::default();
+    let mut changes = Vec::new();
+
+    // The lines with their line endings, so those come through untouched
+    let raw: Vec<&str> = contents.split_inclusive('\n').collect();
+    for m in matcher.search(&contents) {
+        stats.matched_lines += 1;
+        stats.matches += m.submatches.len().max(1);
+
+        let index = m.line_number - 1;
+        let (mut replaced, _) = template.replace(&m);
+        replaced.push_str(&raw[index][m.line.len()..]);
+        if replaced != raw[index] {
+            changes.push((index, replaced));
+        }
+    }
+
+    if changes.is_empty() {
+        return Ok(stats);
+    }
+
+    if config.diff {
+        write_diff(&mut out, path, &raw, &changes)?;
+    } else {
+        let mut changes = changes.iter().peekable();
+        let mut rewritten = String::with_capacity(contents.len());
+        for (index, line) in raw.iter().enumerate() {
+            match changes.next_if(|(changed, _)| *changed == index) {
+                Some((_, replaced)) => rewritten.push_str(replaced),
+                None => rewritten.push_str(line),
+            }
+        }
+        write_atomically(Path::new(path), &rewritten)?;
+    }
+
+    Ok(stats)
+}
+
+// `changes` are (index into `raw`, what that line becomes), in order. A replacement can
+// contain line breaks, so one old line may turn into several new ones.
+fn write_diff<W: Write>(out: &mut W, path: &str, raw: &[&str], changes: &[(usize, String)]) -> io::Result<()> {
+    writeln!(out, "--- {path}")?;
+    writeln!(out, "+++ {path}")?;
+
+    // How many more lines the new file has than the old one, up to the current hunk
+    let mut shift = 0isize;
+
+    let mut first = 0;
+    while first < changes.len() {
+        // Changes close enough that their context would touch go in the same hunk
+        let mut last = first;
+        while last + 1 < changes.len() && changes[last + 1].0 - changes[last].0 <= 2 * CONTEXT + 1 {
+            last += 1;
+        }
+        let hunk = &changes[first..=last];
+
+        let start = hunk[0].0.saturating_sub(CONTEXT);
+        let end = (hunk[hunk.len() - 1].0 + 1 + CONTEXT).min(raw.len());
+        let added: usize = hunk.iter().map(|(_, replaced)| replaced.split_inclusive('\n').count()).sum();
+        let old_len = end - start;
+        let new_len = old_len - hunk.len() + added;
+
+        writeln!(out, "@@ -{},{old_len} +{},{new_len} @@", start + 1, start as isize + shift + 1)?;
+
+        // Like `diff -u`, a run of changed lines shows all the old ones and then all the new ones
+        let mut hunk = hunk.iter().peekable();
+        let mut pending = Vec::new();
+        for (index, line) in raw.iter().enumerate().take(end).skip(start) {
+            match hunk.next_if(|(changed, _)| *changed == index) {
+                Some((_, replaced)) => {
+                    write_diff_lines(out, '-', line)?;
+                    pending.push(replaced);
+                }
+                None => {
+                    for replaced in pending.drain(..) {
+                        write_diff_lines(out, '+', replaced)?;
+                    }
+                    write_diff_lines(out, ' ', line)?;
+                }
+            }
+        }
+        for replaced in pending {
+            write_diff_lines(out, '+', replaced)?;
+        }
+
+        shift += added as isize - (last + 1 - first) as isize;
+        first = last + 1;
+    }
+
+    Ok(())
+}
+
+fn write_diff_lines<W: Write>(out: &mut W, prefix: char, text: &str) -> io::Result<()> {
+    for line in text.split_inclusive('\n') {
+        write!(out, "{prefix}{line}")?;
+        if !line.ends_with('\n') {
+            writeln!(out, "\n\\ No newline at end of file")?;
+        }
+    }
+    Ok(())
+}
+
+// Write to a temporary file next to `path`, then rename it over the original,
+// so anyone reading `path` sees either the old contents or the new ones, never half of each
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    // Rewrite whatever a symlink points at, rather than replacing the link with a plain file
+    let path = fs::canonicalize(path)?;
+    let permissions = fs::metadata(&path)?.permissions();
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{file_name}.minigrep-{}", process::id()));
+
+    let result = File::create_new(&temp).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.set_permissions(permissions)?;
+        file.sync_all()?;
+        fs::rename(&temp, &path)
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn config(flags: &[&str]) -> Config {
+        let mut args = vec!["minigrep", "--no-config"];
+        args.extend(flags);
+        args.extend(["cat", "pets.txt"]);
+        Config::build(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    // Replace "cat" in `line`, returning the new line and where the replacement went
+    fn replace(template: &str, line: &str) -> (String, Range<usize>) {
+        let start = line.find("cat").unwrap();
+        let m = Match { line_number: 1, byte_offset: 0, line, submatches: vec![Range { start, end: start + 3 }] };
+        let (replaced, ranges) = Template::new(template).unwrap().replace(&m);
+        (replaced, ranges[0].clone())
+    }
+
+    #[test]
+    fn templates() {
+        assert_eq!(("the dog sat".to_string(), 4..7), replace("dog", "the cat sat"));
+        assert_eq!(("the [cat] sat".to_string(), 4..9), replace("[$0]", "the cat sat"));
+        assert_eq!(("the cats sat".to_string(), 4..8), replace("${0}s", "the cat sat"));
+        assert_eq!(("the $5 sat".to_string(), 4..6), replace("$$5", "the cat sat"));
+        assert_eq!(("the $ sat".to_string(), 4..5), replace("$", "the cat sat"));
+
+        assert!(Template::new("$1").is_err());
+        assert!(Template::new("${name}").is_err());
+    }
+
+    #[test]
+    fn unified_diff() {
+        let raw: Vec<&str> = "1\n2\n3\n4\ncat\n6\n7\n8\n9\n10\n11\n12\ncat\n14".split_inclusive('\n').collect();
+        let changes = [(4, "dog\n".to_string()), (12, "dog\nbone\n".to_string())];
+
+        let mut out = Vec::new();
+        write_diff(&mut out, "pets.txt", &raw, &changes).unwrap();
+        assert_eq!(
+            "\
+--- pets.txt
++++ pets.txt
+@@ -2,7 +2,7 @@
+ 2
+ 3
+ 4
+-cat
++dog
+ 6
+ 7
+ 8
+@@ -10,5 +10,6 @@
+ 10
+ 11
+ 12
+-cat
++dog
++bone
+ 14
+\\ No newline at end of file
+",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn rewrites_in_place() {
+        let path = env::temp_dir().join(format!("minigrep-replace-{}.txt", process::id()));
+        fs::write(&path, "cat\r\nno match\ncat and cat").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let config = config(&["--replace", "dog", "--in-place"]);
+        let matcher = Matcher::new(&["cat"], false);
+        let template = Template::new("dog").unwrap();
+        let stats = rewrite(&config, &matcher, &template, &path, io::sink()).unwrap();
+
+        let rewritten = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!("dog\r\nno match\ndog and dog", rewritten);
+        assert_eq!(Stats { matched_lines: 2, matches: 3 }, stats);
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::time::Instant;
+
+use crate::input::{self, Input};
+use crate::matcher::Matcher;
+use crate::{json, Config, Match, Stats, Summary};
+
+// `--stats`, `--histogram UNIT` and `--top N`: instead of the matching lines, print what they add
+// up to. That's a table by default, or JSON Lines records with `--json`:
+//
+//   FILE        LINES   MATCHES
+//   app.log         3         4
+//   2 files searched, 1 with matches: 3 lines, 4 matches
+//
+//   TIME                 LINES
+//   2024-05-01 10:00         2  ########################################
+//   2024-05-01 11:00         1  ####################
+//
+//   TOP MATCHES     COUNT
+//   error               3
+//   timeout             1
+//
+// The histogram goes by a timestamp at the start of each matching line (`2024-05-01 10:15:42`,
+// `2024-05-01T10:15:42Z`, `[2024-05-01 10:15]` and so on). Lines without one are counted separately.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bucket {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Bucket {
+    pub fn from_name(name: &str) -> Option<Bucket> {
+        match name {
+            "minute" => Some(Bucket::Minute),
+            "hour" => Some(Bucket::Hour),
+            "day" => Some(Bucket::Day),
+            _ => None,
+        }
+    }
+}
+
+// Widest a histogram bar gets
+const BAR_WIDTH: usize = 40;
+
+struct Report {
+    summary: Summary,
+    // Files with matches, in the order they were given
+    files: Vec<(String, Stats)>,
+    histogram: Option<Bucket>,
+    // Matching lines in each bucket, keyed by the bucket's start so they sort in time order
+    times: BTreeMap<String, usize>,
+    undated: usize,
+    top: Option<usize>,
+    texts: HashMap<String, usize>,
+}
+
+// Says whether anything matched, the same as `crate::run`
+pub fn run(config: &Config, matcher: &Matcher) -> Result<bool, Box<dyn Error>> {
+    let started = Instant::now();
+    let mut report = Report::new(config);
+
+    for path in &config.file_paths {
+        match report.search(config, matcher, path) {
+            Ok(stats) => {
+                report.summary.add(&stats);
+                if stats.matched_lines > 0 {
+                    report.files.push((path.clone(), stats));
+                }
+            }
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                report.summary.files_failed += 1;
+            }
+        }
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let written = if config.json {
+        report.write_json(&mut out).and_then(|_| json::summary(&mut out, &report.summary, started.elapsed()))
+    } else {
+        report.write_table(&mut out)
+    };
+    match written {
+        // Whoever was reading our output has hung up
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(true),
+        written => written?,
+    }
+
+    if report.summary.files_failed > 0 {
+        return Err(format!("{} of {} files could not be searched", report.summary.files_failed, config.file_paths.len()).into());
+    }
+    Ok(report.summary.files_with_matches > 0)
+}
+
+impl Report {
+    fn new(config: &Config) -> Report {
+        Report {
+            summary: Summary::default(),
+            files: Vec::new(),
+            histogram: config.histogram,
+            times: BTreeMap::new(),
+            undated: 0,
+            top: config.top,
+            texts: HashMap::new(),
+        }
+    }
+
+    // Count up one file's matches, the same ones a normal search would print
+    fn search(&mut self, config: &Config, matcher: &Matcher, path: &str) -> io::Result<Stats> {
+        let Input { mut reader, source } = input::open_text(path, config.search_zip, config.encoding)?;
+        let mut stats = Stats::default();
+
+        if config.fuzzy || config.multiline {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes)?;
+            let contents = String::from_utf8_lossy(&bytes);
+            let found = if config.fuzzy {
+                matcher.rank(&contents).into_iter().map(|(_, m)| m).collect()
+            } else {
+                matcher.search_multiline(&contents)
+            };
+            for m in &found {
+                self.record(&mut stats, m);
+            }
+            return Ok(stats);
+        }
+
+        input::for_each_block(reader, source, input::BLOCK_SIZE, |block| {
+            for m in matcher.search(block.contents) {
+                self.record(&mut stats, &m);
+            }
+            Ok(())
+        })?;
+        Ok(stats)
+    }
+
+    fn record(&mut self, stats: &mut Stats, m: &Match) {
+        // Counted the way the printer counts them, so the totals agree with a normal search
+        stats.matched_lines += 1 + m.line.matches('\n').count();
+        stats.matches += m.submatches.len().max(1);
+
+        if let Some(bucket) = self.histogram {
+            match time_bucket(m.line, bucket) {
+                Some(time) => *self.times.entry(time).or_default() += 1,
+                None => self.undated += 1,
+            }
+        }
+        if self.top.is_some() {
+            for range in &m.submatches {
+                *self.texts.entry(m.line[range.clone()].to_string()).or_default() += 1;
+            }
+        }
+    }
+
+    // The `top` most frequent matched texts, most frequent first; ties go alphabetically
+    fn top_texts(&self) -> Vec<(&str, usize)> {
+        let mut texts: Vec<(&str, usize)> = self.texts.iter().map(|(text, &count)| (text.as_str(), count)).collect();
+        texts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        texts.truncate(self.top.unwrap_or(0));
+        texts
+    }
+
+    fn write_table<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let width = self.files.iter().map(|(path, _)| path.chars().count()).max().unwrap_or(0).max(4);
+        writeln!(out, "{:width$}  {:>8}  {:>8}", "FILE", "LINES", "MATCHES")?;
+        for (path, stats) in &self.files {
+            writeln!(out, "{path:width$}  {:>8}  {:>8}", stats.matched_lines, stats.matches)?;
+        }
+        let summary = &self.summary;
+        writeln!(
+            out,
+            "{} searched, {} with matches: {}, {}",
+            counted(summary.files_searched, "file", "files"),
+            summary.files_with_matches,
+            counted(summary.stats.matched_lines, "line", "lines"),
+            counted(summary.stats.matches, "match", "matches"),
+        )?;
+
+        if self.histogram.is_some() {
+            let most = self.times.values().copied().max().unwrap_or(0);
+            writeln!(out, "\n{:16}  {:>8}", "TIME", "LINES")?;
+            for (time, &count) in &self.times {
+                writeln!(out, "{time:16}  {count:>8}  {}", "#".repeat((count * BAR_WIDTH).div_ceil(most)))?;
+            }
+            if self.undated > 0 {
+                writeln!(out, "{:16}  {:>8}", "(no timestamp)", self.undated)?;
+            }
+        }
+
+        if self.top.is_some() {
+            let top = self.top_texts();
+            let width = top.iter().map(|(text, _)| text.chars().count()).max().unwrap_or(0).max(11);
+            writeln!(out, "\n{:width$}  {:>8}", "TOP MATCHES", "COUNT")?;
+            for (text, count) in top {
+                writeln!(out, "{text:width$}  {count:>8}")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (path, stats) in &self.files {
+            json::end(out, path, stats)?;
+        }
+        if self.histogram.is_some() {
+            for (time, &count) in &self.times {
+                json::histogram(out, Some(time), count)?;
+            }
+            if self.undated > 0 {
+                json::histogram(out, None, self.undated)?;
+            }
+        }
+        for (text, count) in self.top_texts() {
+            json::top(out, text, count)?;
+        }
+        Ok(())
+    }
+}
+
+// `1 file`, `2 files`
+fn counted(count: usize, one: &str, many: &str) -> String {
+    format!("{count} {}", if count == 1 { one } else { many })
+}
+
+// The bucket a line's leading timestamp falls in, written the way it sorts:
+// `2024-05-01`, `2024-05-01 10:00` or `2024-05-01 10:15`
+fn time_bucket(line: &str, bucket: Bucket) -> Option<String> {
+    let line = line.trim_start_matches(['[', ' ']);
+    let bytes = line.as_bytes();
+    let digits = |from: usize, to: usize| bytes.get(from..to).is_some_and(|d| d.iter().all(u8::is_ascii_digit));
+    let at = |index: usize, expected: &[u8]| bytes.get(index).is_some_and(|b| expected.contains(b));
+
+    if !(digits(0, 4) && at(4, b"-") && digits(5, 7) && at(7, b"-") && digits(8, 10)) {
+        return None;
+    }
+    let date = &line[..10];
+    if bucket == Bucket::Day {
+        return Some(date.to_string());
+    }
+
+    if !(at(10, b"T ") && digits(11, 13) && at(13, b":") && digits(14, 16)) {
+        return None;
+    }
+    match bucket {
+        Bucket::Hour => Some(format!("{date} {}:00", &line[11..13])),
+        _ => Some(format!("{date} {}", &line[11..16])),
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        let line = "2024-05-01T10:15:42Z error: timeout";
+        assert_eq!(Some("2024-05-01".to_string()), time_bucket(line, Bucket::Day));
+        assert_eq!(Some("2024-05-01 10:00".to_string()), time_bucket(line, Bucket::Hour));
+        assert_eq!(Some("2024-05-01 10:15".to_string()), time_bucket("[2024-05-01 10:15] x", Bucket::Minute));
+        assert_eq!(None, time_bucket("2024-05-01 error", Bucket::Hour));
+        assert_eq!(None, time_bucket("May  1 10:15:42 host error", Bucket::Day));
+    }
+
+    #[test]
+    fn adds_up_matches() {
+        let args: Vec<String> = ["minigrep", "--no-config", "--histogram", "hour", "--top", "2", "-e", "error", "-e", "timeout", "log"]
+            .iter().map(|arg| arg.to_string()).collect();
+        let config = Config::build(&args).unwrap();
+        let matcher = Matcher::new(&config.queries, false);
+        let mut report = Report::new(&config);
+
+        let contents = "2024-05-01 10:01 error: timeout\n2024-05-01 10:59 error\n2024-05-01 11:00 ok\nerror again\n";
+        let mut stats = Stats::default();
+        for m in matcher.search(contents) {
+            report.record(&mut stats, &m);
+        }
+        report.summary.add(&stats);
+        report.files.push(("log".to_string(), stats));
+
+        assert_eq!(Stats { matched_lines: 3, matches: 4 }, stats);
+        assert_eq!(vec![("error", 3), ("timeout", 1)], report.top_texts());
+
+        let mut out = Vec::new();
+        report.write_table(&mut out).unwrap();
+        assert_eq!(
+            "\
+FILE     LINES   MATCHES
+log          3         4
+1 file searched, 1 with matches: 3 lines, 4 matches
+
+TIME                 LINES
+2024-05-01 10:00         2  ########################################
+(no timestamp)           1
+
+TOP MATCHES     COUNT
+error               3
+timeout             1
+",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        report.write_json(&mut out).unwrap();
+        assert_eq!(
+            "\
+{\"type\":\"end\",\"path\":\"log\",\"matched_lines\":3,\"matches\":4}
+{\"type\":\"histogram\",\"time\":\"2024-05-01 10:00\",\"matched_lines\":2}
+{\"type\":\"histogram\",\"time\":null,\"matched_lines\":1}
+{\"type\":\"top\",\"text\":\"error\",\"count\":3}
+{\"type\":\"top\",\"text\":\"timeout\",\"count\":1}
+",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
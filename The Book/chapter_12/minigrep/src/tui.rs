@@ -0,0 +1,519 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{input, Config};
+
+// `--tui`: browse the results in the terminal, editing the query as we go.
+//
+//   > query                                        12 matches
+//   src/lib.rs:10: the matching line                            <- results, selected one highlighted
+//   ...
+//   ── src/lib.rs:10 ──────────────────────────────────────────
+//     8  a few lines either side                                <- preview
+//    10  the matching line
+//
+// Typing edits the query and searches again; Up/Down (or Ctrl-P/Ctrl-N) and Page Up/Down move
+// through the results; Enter opens the selected one in $VISUAL or $EDITOR; Ctrl-L redraws the
+// screen; Esc or Ctrl-C quits.
+// There's no terminal library to lean on, so raw mode is `stty` and drawing is ANSI escapes.
+// Asking `stty` for the size means running it, so that's only done when the terminal says it's
+// been resized (SIGWINCH), or we redraw from scratch.
+
+const SELECTED: &str = "\x1b[7m";
+const MATCHED_TEXT: &str = "\x1b[1;31m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+// Re-searching on every key has to stay quick, so stop collecting results after this many
+const MAX_HITS: usize = 10_000;
+
+// Set when the terminal's been resized since we last asked how big it is
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Backspace,
+    // Ctrl-U, like a shell
+    ClearQuery,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Enter,
+    // Ctrl-L
+    Redraw,
+    Quit,
+}
+
+// One result, pointing into the contents of one of the files
+struct Hit {
+    file: usize,
+    line_number: usize,
+    // Where the line is in the file's contents
+    line: Range<usize>,
+    submatches: Vec<Range<usize>>,
+}
+
+struct State {
+    query: String,
+    hits: Vec<Hit>,
+    selected: usize,
+    // First result shown in the list
+    scroll: usize,
+    // Shown instead of the match count, for things like a bad query
+    status: Option<String>,
+}
+
+pub fn run(config: &Config) -> io::Result<()> {
+    // Read everything up front; every new query searches it all again
+    let mut files = Vec::new();
+    for path in &config.file_paths {
+        let mut bytes = Vec::new();
+        input::open_text(path, config.search_zip, config.encoding)?.reader.read_to_end(&mut bytes)?;
+        files.push((path.clone(), String::from_utf8_lossy(&bytes).into_owned()));
+    }
+
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")
+        .map_err(|e| io::Error::new(e.kind(), format!("--tui needs a terminal: {e}")))?;
+    let mut terminal = Terminal::enter(tty)?;
+    watch_for_resizes();
+    let mut size = terminal_size();
+
+    let mut state = State {
+        query: config.queries.first().cloned().unwrap_or_default(),
+        hits: Vec::new(),
+        selected: 0,
+        scroll: 0,
+        status: None,
+    };
+    search(config, &files, &mut state);
+
+    loop {
+        if RESIZED.swap(false, Ordering::SeqCst) {
+            size = terminal_size();
+        }
+        let (rows, cols) = size;
+        let frame = render(&state, &files, rows, cols);
+        terminal.tty.write_all(frame.as_bytes())?;
+        terminal.tty.flush()?;
+
+        let keys = read_keys(&mut terminal.tty)?;
+        let list_height = list_height(rows);
+        let mut query_changed = false;
+
+        for key in keys {
+            match key {
+                Key::Quit => return Ok(()),
+                Key::Char(c) => {
+                    state.query.push(c);
+                    query_changed = true;
+                }
+                Key::Backspace => query_changed |= state.query.pop().is_some(),
+                Key::ClearQuery => {
+                    query_changed = !state.query.is_empty();
+                    state.query.clear();
+                }
+                Key::Up => state.selected = state.selected.saturating_sub(1),
+                Key::Down => state.selected += 1,
+                Key::PageUp => state.selected = state.selected.saturating_sub(list_height),
+                Key::PageDown => state.selected += list_height,
+                Key::Enter => {
+                    if let Some(hit) = state.hits.get(state.selected) {
+                        let path = &files[hit.file].0;
+                        terminal.suspend()?;
+                        let opened = open_in_editor(path, hit.line_number);
+                        terminal.resume()?;
+                        state.status = opened.err().map(|e| e.to_string());
+                        // The terminal might have changed while the editor had it
+                        size = terminal_size();
+                    }
+                }
+                Key::Redraw => {
+                    terminal.tty.write_all(b"\x1b[2J")?;
+                    size = terminal_size();
+                }
+            }
+        }
+
+        if query_changed {
+            search(config, &files, &mut state);
+        }
+        state.selected = state.selected.min(state.hits.len().saturating_sub(1));
+        state.scroll = scroll_to(state.selected, state.scroll, list_height);
+    }
+}
+
+// Run the current query over every file, the same way a normal search would
+fn search(config: &Config, files: &[(String, String)], state: &mut State) {
+    state.hits.clear();
+    state.selected = 0;
+    state.scroll = 0;
+    state.status = None;
+
+    // Typed queries get the same treatment the command line's did
+    let query = if config.multiline { crate::unescape(&state.query) } else { state.query.clone() };
+    let matcher = match crate::matcher_for(config, &[query]) {
+        Ok(matcher) => matcher,
+        Err(e) => {
+            state.status = Some(e.to_string());
+            return;
+        }
+    };
+
+    for (file, (_, contents)) in files.iter().enumerate() {
+        let found = if config.fuzzy {
+            matcher.rank(contents).into_iter().map(|(_, m)| m).collect()
+        } else if config.multiline {
+            matcher.search_multiline(contents)
+        } else {
+            matcher.search(contents)
+        };
+        for m in found {
+            if state.hits.len() == MAX_HITS {
+                state.status = Some(format!("showing the first {MAX_HITS} matches"));
+                return;
+            }
+            state.hits.push(Hit {
+                file,
+                line_number: m.line_number,
+                line: m.byte_offset..m.byte_offset + m.line.len(),
+                submatches: m.submatches,
+            });
+        }
+    }
+}
+
+// Rows left for the result list once the prompt and preview have theirs
+fn list_height(rows: usize) -> usize {
+    (rows.saturating_sub(2) / 2).max(1)
+}
+
+// Scroll the list just far enough that the selected result is on screen
+fn scroll_to(selected: usize, scroll: usize, height: usize) -> usize {
+    if selected < scroll {
+        selected
+    } else if selected >= scroll + height {
+        selected + 1 - height
+    } else {
+        scroll
+    }
+}
+
+// Draw every row over the top of the last frame, clearing what's left of each one,
+// so the screen never goes blank in between
+fn render(state: &State, files: &[(String, String)], rows: usize, cols: usize) -> String {
+    let mut lines = Vec::with_capacity(rows);
+
+    // Prompt, with the count (or whatever's wrong) on the right
+    let status = match &state.status {
+        Some(status) => status.clone(),
+        None => format!("{} matches", state.hits.len()),
+    };
+    let prompt = format!("> {}", state.query);
+    let gap = cols.saturating_sub(prompt.chars().count() + status.chars().count()).max(1);
+    lines.push(fit(&format!("{prompt}{}{DIM}{status}{RESET}", " ".repeat(gap)), cols));
+
+    let height = list_height(rows);
+    for i in state.scroll..state.scroll + height {
+        let Some(hit) = state.hits.get(i) else {
+            lines.push(String::new());
+            continue;
+        };
+        let (path, contents) = &files[hit.file];
+        let prefix = format!("{path}:{}: ", hit.line_number);
+        let text = first_line(&contents[hit.line.clone()]);
+        let mut shown = highlight(text, &hit.submatches, cols.saturating_sub(prefix.chars().count()));
+        if i == state.selected {
+            // Keep the reverse video on through the highlights' resets
+            shown = shown.replace(RESET, &format!("{RESET}{SELECTED}"));
+            lines.push(format!("{SELECTED}{}{shown}{RESET}", fit(&prefix, cols)));
+        } else {
+            lines.push(format!("{DIM}{}{RESET}{shown}", fit(&prefix, cols)));
+        }
+    }
+
+    // Preview: the selected line in the middle, with whatever fits around it
+    let selected = state.hits.get(state.selected);
+    let title = selected.map_or(String::new(), |hit| format!(" {}:{} ", files[hit.file].0, hit.line_number));
+    lines.push(fit(&format!("{DIM}──{title}{}{RESET}", "─".repeat(cols.saturating_sub(title.chars().count() + 2))), cols));
+
+    let preview_height = rows.saturating_sub(height + 2);
+    if let Some(hit) = selected {
+        let contents = &files[hit.file].1;
+        let first = hit.line_number.saturating_sub(preview_height / 2).max(1);
+        for (number, text) in contents.lines().enumerate().skip(first - 1).take(preview_height) {
+            let number = number + 1;
+            let gutter = format!("{number:>6}  ");
+            let width = cols.saturating_sub(gutter.len());
+            let text = if number == hit.line_number {
+                highlight(text, &hit.submatches, width)
+            } else {
+                fit(text, width)
+            };
+            lines.push(format!("{DIM}{gutter}{RESET}{text}"));
+        }
+    }
+
+    let mut frame = String::new();
+    for row in 1..=rows {
+        let text = lines.get(row - 1).map_or("", String::as_str);
+        let _ = write!(frame, "\x1b[{row};1H{text}\x1b[K");
+    }
+
+    // Leave the cursor at the end of the query, where typing goes
+    let _ = write!(frame, "\x1b[1;{}H", state.query.chars().count() + 3);
+    frame
+}
+
+// A multiline match is listed by its first line
+fn first_line(text: &str) -> &str {
+    text.split('\n').next().unwrap_or(text).trim_end_matches('\r')
+}
+
+// At most `width` characters of `text`, with tabs and other control characters made harmless
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted = String::new();
+    let mut in_escape = false;
+    let mut shown = 0;
+    for c in text.chars() {
+        // Our own escapes (colors) don't take up any room
+        if c == '\x1b' || in_escape {
+            in_escape = c != 'm';
+            fitted.push(c);
+            continue;
+        }
+        if shown == width {
+            break;
+        }
+        fitted.push(if c.is_control() { ' ' } else { c });
+        shown += 1;
+    }
+    fitted
+}
+
+// `text` cut to `width`, with the submatches in color
+fn highlight(text: &str, submatches: &[Range<usize>], width: usize) -> String {
+    let mut painted = String::new();
+    let mut last = 0;
+    for range in submatches {
+        let (start, end) = (range.start.min(text.len()), range.end.min(text.len()));
+        if start < last || !text.is_char_boundary(start) || !text.is_char_boundary(end) {
+            continue;
+        }
+        painted.push_str(&text[last..start]);
+        let _ = write!(painted, "{MATCHED_TEXT}{}{RESET}", &text[start..end]);
+        last = end;
+    }
+    painted.push_str(&text[last..]);
+    fit(&painted, width)
+}
+
+// Wait for at least one key, then take everything that's arrived. Gives up with no keys if the
+// terminal's resized in the meantime, so the screen can be drawn again to fit.
+fn read_keys(tty: &mut File) -> io::Result<Vec<Key>> {
+    let mut buf = [0; 64];
+    loop {
+        // Raw mode is set up so this gives up after a tenth of a second with nothing read
+        match tty.read(&mut buf) {
+            Ok(0) => {}
+            Ok(read) => return Ok(parse_keys(&buf[..read])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+        if RESIZED.load(Ordering::SeqCst) {
+            return Ok(Vec::new());
+        }
+    }
+}
+
+fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let text = String::from_utf8_lossy(bytes);
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' => match (chars.next_if_eq(&'['), chars.peek()) {
+                // A lone Esc; a key's escape sequence always arrives all at once
+                (None, _) => Key::Quit,
+                (Some(_), Some('A')) => Key::Up,
+                (Some(_), Some('B')) => Key::Down,
+                (Some(_), Some('5')) => Key::PageUp,
+                (Some(_), Some('6')) => Key::PageDown,
+                // Something we don't handle; skip to the end of it
+                (Some(_), _) => {
+                    while chars.next_if(|c| !c.is_ascii_alphabetic() && *c != '~').is_some() {}
+                    chars.next();
+                    continue;
+                }
+            },
+            '\x03' | '\x11' => Key::Quit,
+            '\x0c' => Key::Redraw,
+            '\x0e' => Key::Down,
+            '\x10' => Key::Up,
+            '\x15' => Key::ClearQuery,
+            '\x7f' | '\x08' => Key::Backspace,
+            '\r' | '\n' => Key::Enter,
+            c if c.is_control() => continue,
+            c => Key::Char(c),
+        };
+
+        // Finish off the escape sequences we recognised
+        match key {
+            Key::Up | Key::Down if c == '\x1b' => {
+                chars.next();
+            }
+            Key::PageUp | Key::PageDown => {
+                chars.next();
+                chars.next_if_eq(&'~');
+            }
+            _ => {}
+        }
+        keys.push(key);
+    }
+
+    keys
+}
+
+// Raw mode and the alternate screen, put back the way they were when this is dropped
+struct Terminal {
+    tty: File,
+    // `stty -g` from before we started, to hand back to `stty` afterwards
+    saved: String,
+}
+
+impl Terminal {
+    fn enter(tty: File) -> io::Result<Terminal> {
+        let saved = stty(&["-g"])?.trim().to_string();
+        let mut terminal = Terminal { tty, saved };
+        terminal.resume()?;
+        Ok(terminal)
+    }
+
+    fn resume(&mut self) -> io::Result<()> {
+        // `min 0 time 1`: reads return after a tenth of a second, whether or not a key came
+        stty(&["raw", "-echo", "min", "0", "time", "1"])?;
+        self.tty.write_all(b"\x1b[?1049h\x1b[H\x1b[2J")
+    }
+
+    fn suspend(&mut self) -> io::Result<()> {
+        self.tty.write_all(b"\x1b[?1049l")?;
+        self.tty.flush()?;
+        stty(&[&self.saved]).map(|_| ())
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.suspend();
+    }
+}
+
+// `stty` works on whatever terminal is its stdin
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(File::open("/dev/tty")?)
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("stty {} failed", args.join(" "))));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Storing to an atomic is about all a signal handler can safely do
+#[cfg(unix)]
+extern "C" fn on_resize(_signal: i32) {
+    RESIZED.store(true, Ordering::SeqCst);
+}
+
+// Without a crate there's no safe wrapper for this, so we go straight to the C library
+#[cfg(unix)]
+unsafe extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+#[cfg(unix)]
+const SIGWINCH: i32 = 28;
+
+#[cfg(unix)]
+fn watch_for_resizes() {
+    // SAFETY: `on_resize` only touches an atomic, which is async-signal-safe
+    unsafe {
+        signal(SIGWINCH, on_resize);
+    }
+}
+
+// Elsewhere the size is only looked at again on Ctrl-L
+#[cfg(not(unix))]
+fn watch_for_resizes() {}
+
+// Rows and columns, from `stty size`; 24 by 80 if it won't say
+fn terminal_size() -> (usize, usize) {
+    let size = stty(&["size"]).unwrap_or_default();
+    let mut numbers = size.split_whitespace().map(|n| n.parse().unwrap_or(0));
+    match (numbers.next(), numbers.next()) {
+        (Some(rows), Some(cols)) if rows > 2 && cols > 0 => (rows, cols),
+        _ => (24, 80),
+    }
+}
+
+// Most editors (vi, vim, nano, emacs, micro, helix, ...) take `+LINE` to start on that line
+fn open_in_editor(path: &str, line_number: usize) -> io::Result<()> {
+    if path == "-" {
+        return Err(io::Error::other("stdin can't be opened in an editor"));
+    }
+
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+    // The editor can come with arguments of its own, like `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+
+    let status = Command::new(program).args(words).arg(format!("+{line_number}")).arg(path).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{program} exited with {status}")));
+    }
+    Ok(())
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(
+            vec![Key::Char('a'), Key::Char('é'), Key::Up, Key::Down, Key::PageDown, Key::Backspace, Key::Enter],
+            parse_keys("aé\x1b[A\x1b[B\x1b[6~\x7f\r".as_bytes())
+        );
+        // Unknown sequences (F1 here) are skipped whole
+        assert_eq!(vec![Key::Char('x')], parse_keys(b"\x1b[11~x"));
+        assert_eq!(vec![Key::Quit], parse_keys(b"\x1b"));
+        assert_eq!(vec![Key::ClearQuery, Key::Quit], parse_keys(b"\x15\x03"));
+        assert_eq!(vec![Key::Redraw], parse_keys(b"\x0c"));
+    }
+
+    #[test]
+    fn scrolling_follows_the_selection() {
+        assert_eq!(0, scroll_to(3, 0, 10));
+        assert_eq!(4, scroll_to(13, 0, 10));
+        assert_eq!(2, scroll_to(2, 5, 10));
+    }
+
+    #[test]
+    fn fitting_text() {
+        assert_eq!("a b", fit("a\tbcd", 3));
+        assert_eq!(format!("x{MATCHED_TEXT}yz{RESET}"), highlight("xyz!", &[Range { start: 1, end: 3 }], 3));
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::{Config, Stats, Summary};
+use crate::matcher::Matcher;
+
+// What one worker found in one file: everything it would have printed, or why it couldn't
+type Output = (usize, Vec<u8>, io::Result<Stats>);
+
+// Search every path in `config` on a pool of `config.threads` workers.
+// Each file's output is buffered so it's never interleaved with another file's.
+// Files are written out as they finish, or in command-line order with `--sort`.
+// Files that couldn't be searched are reported on stderr and counted in the summary.
+pub fn search_files<W: Write>(config: &Config, matcher: &Matcher, out: &mut W) -> io::Result<Summary> {
+    let paths = &config.file_paths;
+    let threads = config.threads.min(paths.len()).max(1);
+
+    // Workers pull the next unclaimed file off this counter until there are none left
+    let next_path = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel::<Output>();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let next_path = &next_path;
+
+            scope.spawn(move || loop {
+                let index = next_path.fetch_add(1, Ordering::Relaxed);
+                if index >= paths.len() {
+                    break;
+                }
+
+                let mut buf = Vec::new();
+                let result = crate::search_path(config, matcher, &paths[index], &mut buf, true);
+
+                // The receiver only goes away if writing our output failed, so stop early
+                if sender.send((index, buf, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        // Only the workers hold senders now, so the loop below ends when they're all done
+        drop(sender);
+
+        let mut summary = Summary::default();
+        let mut pending = BTreeMap::new();
+        let mut next_to_write = 0;
+
+        for (index, buf, result) in receiver {
+            if !config.sort {
+                write_output(out, &paths[index], &buf, result, &mut summary)?;
+                continue;
+            }
+
+            // Hold on to anything that finished early until everything before it is written
+            pending.insert(index, (buf, result));
+            while let Some((buf, result)) = pending.remove(&next_to_write) {
+                write_output(out, &paths[next_to_write], &buf, result, &mut summary)?;
+                next_to_write += 1;
+            }
+        }
+
+        Ok(summary)
+    })
+}
+
+fn write_output<W: Write>(
+    out: &mut W,
+    path: &str,
+    buf: &[u8],
+    result: io::Result<Stats>,
+    summary: &mut Summary,
+) -> io::Result<()> {
+    out.write_all(buf)?;
+
+    match result {
+        Ok(stats) => summary.add(&stats),
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            summary.files_failed += 1;
+        }
+    }
+
+    Ok(())
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn sorted_output_follows_argument_order() {
+        let dir = env::temp_dir().join(format!("minigrep-workers-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut paths = Vec::new();
+        for i in 0..8 {
+            let path = dir.join(format!("{i}.txt"));
+            fs::write(&path, format!("line {i}\nnothing here\nline {i} again\n")).unwrap();
+            paths.push(path.to_string_lossy().into_owned());
+        }
+        let missing = dir.join("missing.txt").to_string_lossy().into_owned();
+
+        let mut argv = args(&["minigrep", "--no-config", "--sort", "-j", "4", "line"]);
+        argv.extend(paths.iter().cloned());
+        argv.push(missing);
+        let config = Config::build(&argv).unwrap();
+        let matcher = Matcher::new(&config.queries, false);
+
+        let mut out = Vec::new();
+        let summary = search_files(&config, &matcher, &mut out).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let expected: String = paths.iter().enumerate()
+            .map(|(i, path)| format!("{path}:line {i}\n{path}:line {i} again\n"))
+            .collect();
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+        assert_eq!((8, 8, 1), (summary.files_searched, summary.files_with_matches, summary.files_failed));
+        assert_eq!(16, summary.stats.matched_lines);
+    }
+}
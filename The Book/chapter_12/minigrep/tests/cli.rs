@@ -0,0 +1,173 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+// These run the real binary against the files in tests/fixtures, the way someone would from a shell,
+// and check what it prints and how it exits (0 for a match, 1 for none, 2 for an error).
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures")
+}
+
+// The binary, run from the fixtures directory with none of the caller's settings leaking in
+fn minigrep(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_minigrep"));
+    command
+        .args(args)
+        .current_dir(fixtures())
+        .env("MINIGREP_CONFIG", "")
+        .env_remove("IGNORE_CASE")
+        .env_remove("NO_COLOR");
+    command
+}
+
+fn run(args: &[&str]) -> Output {
+    minigrep(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+fn stderr(output: &Output) -> &str {
+    std::str::from_utf8(&output.stderr).unwrap()
+}
+
+#[test]
+fn prints_matching_lines() {
+    let output = run(&["nobody", "poem.txt"]);
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("I'm nobody! Who are you?\nAre you nobody, too?\n", stdout(&output));
+    assert_eq!("", stderr(&output));
+}
+
+#[test]
+fn no_match_exits_with_one() {
+    let output = run(&["zebra", "poem.txt"]);
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!("", stdout(&output));
+    assert_eq!("", stderr(&output));
+}
+
+#[test]
+fn ignore_case_from_the_environment() {
+    let output = run(&["HOW", "poem.txt"]);
+    assert_eq!(Some(1), output.status.code());
+
+    let output = minigrep(&["HOW", "poem.txt"]).env("IGNORE_CASE", "1").output().unwrap();
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("How dreary to be somebody!\nHow public, like a frog\n", stdout(&output));
+}
+
+#[test]
+fn missing_file_is_an_error() {
+    let output = run(&["nobody", "missing.txt"]);
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!("", stdout(&output));
+    assert!(stderr(&output).contains("missing.txt"), "{}", stderr(&output));
+
+    // The files that are there still get searched, but it's still an error
+    let output = run(&["--sort", "nobody", "poem.txt", "missing.txt"]);
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!("poem.txt:I'm nobody! Who are you?\npoem.txt:Are you nobody, too?\n", stdout(&output));
+    assert!(stderr(&output).contains("1 of 2 files could not be searched"), "{}", stderr(&output));
+}
+
+#[test]
+fn no_color_beats_the_config_file() {
+    let config = std::env::temp_dir().join(format!("minigrep-cli-config-{}.toml", std::process::id()));
+    std::fs::write(&config, "flags = [\"--color=always\"]\n").unwrap();
+    let colored = |args: &[&str], no_color: &str| {
+        let output = minigrep(args).env("MINIGREP_CONFIG", &config).env("NO_COLOR", no_color).output().unwrap();
+        assert_eq!(Some(0), output.status.code());
+        stdout(&output).contains('\x1b')
+    };
+
+    assert!(colored(&["nobody", "poem.txt"], ""));
+    assert!(!colored(&["nobody", "poem.txt"], "1"));
+    // The command line beats both
+    assert!(colored(&["--color=always", "nobody", "poem.txt"], "1"));
+    std::fs::remove_file(&config).unwrap();
+}
+
+#[test]
+fn index_is_managed_with_flags() {
+    let dir = std::env::temp_dir().join(format!("minigrep-cli-index-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("build"), "an index card\n").unwrap();
+
+    // `index` and `build` are just a query and a file
+    let output = minigrep(&["index", "build"]).current_dir(&dir).output().unwrap();
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("an index card\n", stdout(&output));
+
+    let output = minigrep(&["--index-build"]).current_dir(&dir).output().unwrap();
+    assert_eq!(Some(0), output.status.code(), "{}", stderr(&output));
+    let output = minigrep(&["--index", "card"]).current_dir(&dir).output().unwrap();
+    assert_eq!(Some(0), output.status.code(), "{}", stderr(&output));
+    assert_eq!("build:an index card\n", stdout(&output));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compressed_files_need_their_tool() {
+    // With nothing on the PATH there's no gzip to decompress with, and the error says so
+    let output = minigrep(&["nobody", "poem.txt.gz"]).env("PATH", "").output().unwrap();
+    assert_eq!(Some(2), output.status.code());
+    assert!(stderr(&output).contains("needs the `gzip` program"), "{}", stderr(&output));
+}
+
+#[test]
+fn bad_arguments_are_an_error() {
+    let output = run(&[]);
+    assert_eq!(Some(2), output.status.code());
+    assert!(stderr(&output).starts_with("Problem parsing arguments: Not enough arguments"), "{}", stderr(&output));
+
+    let output = run(&["-A"]);
+    assert_eq!(Some(2), output.status.code());
+    assert!(stderr(&output).contains("Missing context length"), "{}", stderr(&output));
+}
+
+#[test]
+fn several_files_are_named() {
+    let output = run(&["--sort", "-n", "-e", "ERROR", "-e", "INFO", "logs/app.log", "logs/old.log"]);
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "\
+logs/app.log:1:2024-05-01 10:01:12 INFO starting up
+logs/app.log:2:2024-05-01 10:02:40 ERROR connection timeout
+logs/old.log:1:2024-04-30 23:59:59 INFO shutting down
+",
+        stdout(&output)
+    );
+}
+
+#[test]
+fn reads_stdin() {
+    let mut child = minigrep(&["frog"]).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(b"a toad\na frog\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("a frog\n", stdout(&output));
+}
+
+#[test]
+fn context_and_json() {
+    let output = run(&["-C", "1", "WARN", "logs/app.log"]);
+    assert_eq!(
+        "\
+2024-05-01 10:02:40 ERROR connection timeout
+2024-05-01 10:03:05 WARN retrying
+2024-05-01 10:03:09 error: giving up
+",
+        stdout(&output)
+    );
+
+    let output = run(&["--json", "WARN", "logs/app.log"]);
+    assert_eq!(Some(0), output.status.code());
+    let records: Vec<&str> = stdout(&output).lines().collect();
+    assert_eq!(4, records.len());
+    assert_eq!(r#"{"type":"begin","path":"logs/app.log"}"#, records[0]);
+    assert!(records[1].starts_with(r#"{"type":"match","path":"logs/app.log","line_number":3,"byte_offset":82,"#), "{}", records[1]);
+    assert!(records[3].starts_with(r#"{"type":"summary","files_searched":1,"files_with_matches":1,"#), "{}", records[3]);
+}
@@ -0,0 +1,378 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+
+use crate::http::{self, Limits, ParseError, Request, Response, Version};
+use crate::router::{Handler, Params};
+use crate::ServerError;
+
+// How long, and how much, we'll read from a client we're hanging up on
+const LINGER: Duration = Duration::from_secs(1);
+const LINGER_BYTES: u64 = 64 * 1024;
+
+// How long a connection is kept open, and how hard it's allowed to work. Each open connection
+// ties up a worker, so an idle one can't be kept waiting for long.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    // How long to wait for the next request to start before hanging up
+    pub idle_timeout: Duration,
+    // How long a client has to send all of a request once it's started, however slowly it sends
+    // it; after that it gets a 408
+    pub request_timeout: Duration,
+    // Requests served on one connection before we close it
+    pub max_requests: usize,
+    pub limits: Limits,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            idle_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            max_requests: 100,
+            limits: Limits::default(),
+        }
+    }
+}
+
+// Answer requests on `stream` for as long as the client keeps the connection open and within
+// `options`. Pipelined requests (sent before the answers to the earlier ones) are answered in
+// order, and their responses are sent together. The client hanging up on us isn't an error; any
+// other problem with the connection is, and the caller should log it and drop the connection.
+pub fn serve(stream: TcpStream, handler: &dyn Handler, options: &Options) -> Result<(), ServerError> {
+    match answer(&stream, handler, options).map_err(ServerError::from) {
+        Err(e) if e.is_disconnect() => Ok(()),
+        result => result,
+    }
+}
+
+fn answer(stream: &TcpStream, handler: &dyn Handler, options: &Options) -> io::Result<()> {
+    let mut reader = BufReader::new(Timed { stream, deadline: None });
+    let mut writer = BufWriter::new(stream);
+    // Whether we're the ones ending the connection, while the client might still be sending
+    let mut hanging_up = false;
+
+    for served in 1.. {
+        // Between requests the client can take its time, up to a point. Pipelined requests are
+        // already here.
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+            reader.get_mut().deadline = None;
+            stream.set_read_timeout(Some(options.idle_timeout))?;
+            match reader.fill_buf() {
+                // The client's finished with us, or has gone quiet for too long
+                Ok([]) => break,
+                Ok(_) => {}
+                Err(e) if timed_out(&e) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        reader.get_mut().deadline = Some(Instant::now() + options.request_timeout);
+        let request = match http::read_request(&mut reader, &options.limits) {
+            Ok(request) => request,
+            Err(ParseError::Closed) => break,
+            // Part of a request and then nothing, or too little too slowly
+            Err(ParseError::Io(e)) if timed_out(&e) => {
+                Response::new(408).with_header("Connection", "close").write_to(&mut writer)?;
+                hanging_up = true;
+                break;
+            }
+            Err(ParseError::Io(e)) => return Err(e),
+            Err(e) => {
+                // Tell the client what was wrong with it. There's no telling where the next
+                // request would start, so that's the end of the connection.
+                if let Some(status) = e.status() {
+                    Response::new(status).with_header("Connection", "close").write_to(&mut writer)?;
+                }
+                hanging_up = true;
+                break;
+            }
+        };
+
+        let keep_alive = wants_keep_alive(&request) && served < options.max_requests && !crate::shutdown_requested();
+
+        let mut response = respond(handler, &request);
+        if request.method == "HEAD" {
+            response = response.without_body();
+        }
+        if !keep_alive {
+            response = response.with_header("Connection", "close");
+        } else if request.version == Version::Http10 {
+            response = response.with_header("Connection", "keep-alive");
+        }
+        response.write_to(&mut writer)?;
+
+        if !keep_alive {
+            hanging_up = true;
+            break;
+        }
+        // More requests already waiting means they were pipelined; they're answered before
+        // anything's flushed
+    }
+
+    writer.flush()?;
+    drop(writer);
+    if hanging_up {
+        linger(stream, reader)?;
+    }
+    Ok(())
+}
+
+// The handler's response, or a 500 if it fails or panics. Either way the request was read in
+// full, so the connection can carry on.
+fn respond(handler: &dyn Handler, request: &Request) -> Response {
+    let error = match panic::catch_unwind(AssertUnwindSafe(|| handler.handle(request, &Params::default()))) {
+        Ok(Ok(response)) => return response,
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => "handler panicked".to_string(),
+    };
+    eprintln!("{} {}: {error}", request.method, request.target);
+    Response::new(500).with_body("text/plain; charset=utf-8", "Internal Server Error\n")
+}
+
+// Closing a socket with unread input makes the OS reset the connection, which can throw away the
+// last response before the client reads it. So say we're done sending, and read (and ignore)
+// whatever else the client sends for a little while first.
+fn linger(stream: &TcpStream, mut reader: BufReader<Timed>) -> io::Result<()> {
+    stream.shutdown(Shutdown::Write)?;
+    reader.get_mut().deadline = None;
+    stream.set_read_timeout(Some(LINGER))?;
+    let _ = io::copy(&mut reader.by_ref().take(LINGER_BYTES), &mut io::sink());
+    Ok(())
+}
+
+fn timed_out(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+// Reads from the connection, and once there's a deadline, gives up when it's passed rather than
+// only when a single read takes too long. Otherwise a client sending a byte every few seconds
+// could hold on to a worker for as long as it liked.
+struct Timed<'a> {
+    stream: &'a TcpStream,
+    deadline: Option<Instant>,
+}
+
+impl Read for Timed<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.stream.set_read_timeout(Some(left))?;
+        }
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+// HTTP/1.1 connections stay open unless the client says `Connection: close`; HTTP/1.0 ones
+// close unless it says `Connection: keep-alive`
+fn wants_keep_alive(request: &Request) -> bool {
+    let has = |option: &str| {
+        request.headers.iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Connection"))
+            .flat_map(|(_, value)| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case(option))
+    };
+    match request.version {
+        Version::Http11 => !has("close"),
+        Version::Http10 => has("keep-alive"),
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Router;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Instant;
+
+    // Serve one connection with `options` on a thread, giving back the client's end and whatever
+    // `serve` came back with
+    fn connect(options: Options) -> (TcpStream, thread::JoinHandle<Result<(), ServerError>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let router = Router::new()
+                .get("/echo/:word", |_: &Request, params: &Params| {
+                    Ok(Response::new(200).with_body("text/plain", params.get("word").unwrap()))
+                })
+                .get("/fail", |_: &Request, _: &Params| Err(ServerError::handler("database is down")))
+                .get("/panic", |_: &Request, _: &Params| panic!("handler bug"));
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, &router, &options)
+        });
+        (TcpStream::connect(address).unwrap(), server)
+    }
+
+    // Send `input` all at once (then say we're done sending, if `close` is set) and give back
+    // everything the server sent before closing the connection
+    fn send(options: Options, input: &[u8], close: bool) -> String {
+        let (mut client, server) = connect(options);
+        client.write_all(input).unwrap();
+        if close {
+            client.shutdown(Shutdown::Write).unwrap();
+        }
+        let mut output = Vec::new();
+        client.read_to_end(&mut output).unwrap();
+        drop(client);
+        server.join().unwrap().unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn exchange(options: Options, input: &str) -> String {
+        send(options, input.as_bytes(), false)
+    }
+
+    fn statuses(output: &str) -> Vec<&str> {
+        output.split("HTTP/1.1 ").skip(1).map(|response| &response[..3]).collect()
+    }
+
+    #[test]
+    fn answers_pipelined_requests_in_order() {
+        let output = exchange(
+            Options::default(),
+            "GET /echo/one HTTP/1.1\r\nHost: a\r\n\r\n\
+             GET /missing HTTP/1.1\r\nHost: a\r\n\r\n\
+             GET /echo/two HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!(vec!["200", "404", "200"], statuses(&output));
+        assert!(output.find("one").unwrap() < output.find("two").unwrap());
+        assert_eq!(1, output.matches("Connection: close").count());
+        assert!(output.ends_with("Connection: close\r\nContent-Length: 3\r\n\r\ntwo"), "{output}");
+    }
+
+    #[test]
+    fn closes_when_asked_or_limited() {
+        // Whatever comes after `Connection: close` is never answered
+        let output = exchange(
+            Options::default(),
+            "GET /echo/a HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\nGET /echo/b HTTP/1.1\r\nHost: a\r\n\r\n",
+        );
+        assert_eq!(vec!["200"], statuses(&output));
+
+        // HTTP/1.0 closes by default, unless it asks to keep the connection open
+        let output = exchange(Options::default(), "GET /echo/a HTTP/1.0\r\n\r\nGET /echo/b HTTP/1.0\r\n\r\n");
+        assert_eq!(vec!["200"], statuses(&output));
+        let output = exchange(
+            Options::default(),
+            "GET /echo/a HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET /echo/b HTTP/1.0\r\n\r\n",
+        );
+        assert_eq!(vec!["200", "200"], statuses(&output));
+        assert!(output.contains("Connection: keep-alive\r\n"));
+
+        let options = Options { max_requests: 2, ..Options::default() };
+        let output = exchange(options, &"GET /echo/x HTTP/1.1\r\nHost: a\r\n\r\n".repeat(3));
+        assert_eq!(vec!["200", "200"], statuses(&output));
+    }
+
+    #[test]
+    fn stops_after_a_bad_request() {
+        let output = exchange(
+            Options::default(),
+            "GET /echo/a HTTP/1.1\r\nHost: a\r\n\r\nGARBAGE\r\n\r\nGET /echo/b HTTP/1.1\r\nHost: a\r\n\r\n",
+        );
+        assert_eq!(vec!["200", "400"], statuses(&output));
+    }
+
+    #[test]
+    fn hangs_up_on_idle_clients() {
+        let options = Options { idle_timeout: Duration::from_millis(100), ..Options::default() };
+        let started = Instant::now();
+        // The connection stays open after this, but nothing else arrives
+        let output = exchange(options, "GET /echo/a HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(vec!["200"], statuses(&output));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn times_out_requests_that_stall() {
+        // Half a request and then nothing: the idle timeout is for between requests, not this
+        let options = Options { idle_timeout: Duration::from_millis(50), request_timeout: Duration::from_millis(200), ..Options::default() };
+        let started = Instant::now();
+        let output = send(options, b"GET /echo/a HTTP/1.1\r\nHo", false);
+        assert_eq!(vec!["408"], statuses(&output));
+        assert!(output.contains("Connection: close\r\n"));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn times_out_requests_that_trickle_in() {
+        // A byte at a time, each well within the idle timeout, never gets the whole request in
+        let options = Options { idle_timeout: Duration::from_millis(200), request_timeout: Duration::from_millis(300), ..Options::default() };
+        let (client, server) = connect(options);
+        let mut writer = client.try_clone().unwrap();
+        let answered = Arc::new(AtomicBool::new(false));
+        let trickle = thread::spawn({
+            let answered = Arc::clone(&answered);
+            move || {
+                for byte in b"GET /echo/a HTTP/1.1\r\nHost: a\r\nX: ".iter().chain(b"x".repeat(1000).iter()) {
+                    if answered.load(Ordering::SeqCst) || writer.write_all(&[*byte]).is_err() {
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                }
+            }
+        });
+
+        let started = Instant::now();
+        let mut output = String::new();
+        (&client).read_to_string(&mut output).unwrap();
+        assert_eq!(vec!["408"], statuses(&output));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        answered.store(true, Ordering::SeqCst);
+        trickle.join().unwrap();
+        drop(client);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn handler_failures_get_a_500() {
+        let output = exchange(
+            Options::default(),
+            "GET /fail HTTP/1.1\r\nHost: a\r\n\r\n\
+             GET /panic HTTP/1.1\r\nHost: a\r\n\r\n\
+             GET /echo/fine HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!(vec!["500", "500", "200"], statuses(&output));
+        assert!(output.ends_with("fine"));
+    }
+
+    #[test]
+    fn malformed_and_truncated_requests() {
+        let bad = |input: &[u8]| statuses(&send(Options::default(), input, true)).concat();
+
+        assert_eq!("400", bad(b"GET /echo/\xff\xfe HTTP/1.1\r\nHost: a\r\n\r\n"));
+        assert_eq!("400", bad(b"\x00\x01\x02\r\n\r\n"));
+        assert_eq!("400", bad(b"GET /echo/a HTTP/1.1\r\nHost: a\r\n"));
+        assert_eq!("400", bad(b"GET /echo/a HTT"));
+        assert_eq!("400", bad(b"POST /echo/a HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\nabc"));
+        assert_eq!("400", bad(b"POST /echo/a HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nab"));
+        assert_eq!("431", bad(format!("GET / HTTP/1.1\r\nHost: a\r\nX: {}\r\n\r\n", "x".repeat(10_000)).as_bytes()));
+        assert_eq!("414", bad(format!("GET /{} HTTP/1.1\r\nHost: a\r\n\r\n", "x".repeat(10_000)).as_bytes()));
+        // Saying nothing at all isn't a bad request, just a short conversation
+        assert_eq!("", bad(b""));
+    }
+
+    #[test]
+    fn clients_hanging_up_early_are_not_errors() {
+        for input in [&b"GET /echo/a HTTP/1.1\r\nHo"[..], b"POST /echo/a HTTP/1.1\r\nHost: a\r\nContent-Length: 99\r\n\r\n"] {
+            let (mut client, server) = connect(Options::default());
+            client.write_all(input).unwrap();
+            drop(client);
+            let result = server.join().unwrap();
+            assert!(result.is_ok(), "{result:?}");
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt;
+use std::io;
+
+use crate::PoolCreationError;
+
+// Everything that can go wrong in the server. Starting up can fail outright; after that, errors
+// only ever cost one request (a handler error becomes a 500) or one connection (it's logged and
+// dropped), never the whole server.
+#[derive(Debug)]
+pub enum ServerError {
+    // Couldn't listen on the address
+    Bind(String, io::Error),
+    Pool(PoolCreationError),
+    // An environment variable that should have been a number: its name and what it was instead
+    NotANumber(String, String),
+    // Reading from or writing to a connection failed
+    Io(io::Error),
+    // A handler couldn't come up with a response
+    Handler(String),
+}
+
+impl ServerError {
+    // For handlers: something went wrong that the client should only hear about as a 500
+    pub fn handler(message: impl fmt::Display) -> ServerError {
+        ServerError::Handler(message.to_string())
+    }
+
+    // The client hung up on us, which is how connections often end and nothing worth reporting
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            ServerError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerError::Bind(address, e) => write!(f, "couldn't listen on {address}: {e}"),
+            ServerError::Pool(e) => write!(f, "{e}"),
+            ServerError::NotANumber(name, value) => write!(f, "{name} should be a number, not {value:?}"),
+            ServerError::Io(e) => write!(f, "{e}"),
+            ServerError::Handler(message) => write!(f, "handler failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServerError::Bind(_, e) | ServerError::Io(e) => Some(e),
+            ServerError::Pool(e) => Some(e),
+            ServerError::NotANumber(..) | ServerError::Handler(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> ServerError {
+        ServerError::Io(e)
+    }
+}
+
+impl From<PoolCreationError> for ServerError {
+    fn from(e: PoolCreationError) -> ServerError {
+        ServerError::Pool(e)
+    }
+}
@@ -0,0 +1,323 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::http::{Body, Request, Response};
+
+// Serves the files under a document root: `/docs/intro.html` is `ROOT/docs/intro.html`. A directory
+// is served as its index file, or as a list of what's in it when that's turned on. Nothing outside
+// the root can be reached, whether by `..`, its percent-encoded spellings or a symlink.
+pub struct StaticFiles {
+    root: PathBuf,
+    index_files: Vec<String>,
+    listings: bool,
+}
+
+// What we tell browsers a file is, by its extension
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("ico", "image/x-icon"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("woff2", "font/woff2"),
+];
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles { root: root.into(), index_files: vec!["index.html".to_string()], listings: false }
+    }
+
+    // List a directory's contents when it has no index file, instead of answering 404
+    pub fn with_listings(mut self, listings: bool) -> StaticFiles {
+        self.listings = listings;
+        self
+    }
+
+    pub fn with_index_files(mut self, names: &[&str]) -> StaticFiles {
+        self.index_files = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn serve(&self, request: &Request) -> Response {
+        let head = match request.method.as_str() {
+            "GET" => false,
+            "HEAD" => true,
+            _ => return Response::new(405).with_header("Allow", "GET, HEAD"),
+        };
+
+        let Some(relative) = relative_path(request.path()) else {
+            return Response::new(403);
+        };
+        let path = self.root.join(&relative);
+
+        // `relative` can't climb out of the root, but a symlink inside it still could
+        let (Ok(root), Ok(real)) = (self.root.canonicalize(), path.canonicalize()) else {
+            return Response::new(404);
+        };
+        if !real.starts_with(&root) {
+            return Response::new(403);
+        }
+
+        if real.is_dir() {
+            // Without the slash, relative links in the page would be resolved from the parent
+            if !request.path().ends_with('/') {
+                return Response::new(301).with_header("Location", &format!("{}/", request.path()));
+            }
+            if let Some(index) = self.index_files.iter().map(|name| real.join(name)).find(|index| index.is_file()) {
+                return file_response(&index, head);
+            }
+            if self.listings {
+                return match listing(&real, request.path()) {
+                    Ok(page) if head => Response::new(200).with_body("text/html; charset=utf-8", page).without_body(),
+                    Ok(page) => Response::new(200).with_body("text/html; charset=utf-8", page),
+                    Err(_) => Response::new(404),
+                };
+            }
+            return Response::new(404);
+        }
+        file_response(&real, head)
+    }
+}
+
+// The request path as a path under the root, or None if it tries to leave it
+fn relative_path(request_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(request_path)?;
+    let mut relative = PathBuf::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            // A backslash is a separator on Windows, and a NUL ends the path for the OS
+            _ if segment.contains(['\\', '\0']) => return None,
+            _ => relative.push(segment),
+        }
+    }
+    Some(relative)
+}
+
+fn file_response(path: &Path, head: bool) -> Response {
+    let opened = File::open(path).and_then(|file| Ok((file.metadata()?.len(), file)));
+    let Ok((len, file)) = opened else {
+        return Response::new(404);
+    };
+
+    let response = Response::new(200).with_header("Content-Type", content_type(path));
+    if head {
+        return response.with_header("Content-Length", &len.to_string());
+    }
+    Response { body: Body::File(file, len), ..response }
+}
+
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    CONTENT_TYPES.iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map_or("application/octet-stream", |(_, content_type)| content_type)
+}
+
+// An HTML page linking to everything in `dir` except hidden files, directories first
+fn listing(dir: &Path, request_path: &str) -> io::Result<String> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with('.') {
+            entries.push((!entry.file_type()?.is_dir(), name));
+        }
+    }
+    entries.sort();
+
+    let title = html_escape(&percent_decode(request_path).unwrap_or_default());
+    let mut page = format!("<!DOCTYPE html>\n<html lang=\"en\">\n<head><meta charset=\"UTF-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<ul>\n");
+    if request_path != "/" {
+        page.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (is_file, name) in entries {
+        let slash = if is_file { "" } else { "/" };
+        page.push_str(&format!("<li><a href=\"{}{slash}\">{}{slash}</a></li>\n", percent_encode(&name), html_escape(&name)));
+    }
+    page.push_str("</ul>\n</body>\n</html>\n");
+    Ok(page)
+}
+
+// `%XX` escapes turned back into the bytes they stand for. None if one's malformed, or the bytes
+// aren't UTF-8.
+pub fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+// A file name made safe to put in a URL path
+fn percent_encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(char::from(b));
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Version;
+    use std::env;
+    use std::process;
+
+    fn get(files: &StaticFiles, method: &str, target: &str) -> Response {
+        let request = Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: Version::Http11,
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        files.serve(&request)
+    }
+
+    fn body(response: Response) -> String {
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        out.split_once("\r\n\r\n").unwrap().1.to_string()
+    }
+
+    // A little site under a fresh directory named after the test, with a secret next to it
+    fn site(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chapter-20-files-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("public/docs")).unwrap();
+        fs::create_dir_all(dir.join("public/empty dir")).unwrap();
+        fs::write(dir.join("public/index.html"), "<h1>home</h1>").unwrap();
+        fs::write(dir.join("public/docs/notes & things.txt"), "notes").unwrap();
+        fs::write(dir.join("public/style.css"), "p {}").unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        dir
+    }
+
+    #[test]
+    fn serves_files_with_their_type() {
+        let dir = site("types");
+        let files = StaticFiles::new(dir.join("public"));
+
+        let response = get(&files, "GET", "/");
+        assert_eq!(200, response.status);
+        assert_eq!(Some("text/html; charset=utf-8"), response.header("Content-Type"));
+        assert_eq!("<h1>home</h1>", body(response));
+
+        let response = get(&files, "GET", "/style.css?v=2");
+        assert_eq!(Some("text/css; charset=utf-8"), response.header("Content-Type"));
+        assert_eq!("notes", body(get(&files, "GET", "/docs/notes%20%26%20things.txt")));
+
+        assert_eq!(405, get(&files, "POST", "/").status);
+        assert_eq!(404, get(&files, "GET", "/missing.html").status);
+        assert_eq!(404, get(&files, "GET", "/docs/").status);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn head_has_no_body() {
+        let dir = site("head");
+        let files = StaticFiles::new(dir.join("public"));
+
+        let response = get(&files, "HEAD", "/style.css");
+        assert_eq!(200, response.status);
+        assert_eq!(Some("4"), response.header("Content-Length"));
+        assert!(response.body.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nothing_outside_the_root() {
+        let dir = site("traversal");
+        let files = StaticFiles::new(dir.join("public"));
+
+        assert_eq!(403, get(&files, "GET", "/../secret.txt").status);
+        assert_eq!(403, get(&files, "GET", "/docs/%2e%2e/%2E%2E/secret.txt").status);
+        assert_eq!(403, get(&files, "GET", "/..%2fsecret.txt").status);
+        assert_eq!(403, get(&files, "GET", "/bad%zz").status);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_the_root_are_forbidden() {
+        let dir = site("symlink");
+        std::os::unix::fs::symlink(dir.join("secret.txt"), dir.join("public/escape.txt")).unwrap();
+        let files = StaticFiles::new(dir.join("public"));
+
+        assert_eq!(403, get(&files, "GET", "/escape.txt").status);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directories_redirect_to_a_trailing_slash() {
+        let dir = site("redirect");
+        let files = StaticFiles::new(dir.join("public"));
+
+        let response = get(&files, "GET", "/docs");
+        assert_eq!(301, response.status);
+        assert_eq!(Some("/docs/"), response.header("Location"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lists_directories_when_asked() {
+        let dir = site("listing");
+        let files = StaticFiles::new(dir.join("public")).with_listings(true);
+
+        let page = body(get(&files, "GET", "/docs/"));
+        assert!(page.contains("<a href=\"../\">../</a>"), "{page}");
+        assert!(page.contains("<a href=\"notes%20%26%20things.txt\">notes &amp; things.txt</a>"), "{page}");
+        let page = body(get(&files, "GET", "/empty%20dir/"));
+        assert!(page.contains("<h1>Index of /empty dir/</h1>"), "{page}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn content_types() {
+        assert_eq!("image/png", content_type(Path::new("a/logo.PNG")));
+        assert_eq!("application/octet-stream", content_type(Path::new("Makefile")));
+        assert_eq!("application/octet-stream", content_type(Path::new("archive.tar.xz")));
+    }
+}
@@ -0,0 +1,528 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+
+// Reading HTTP/1.x requests off a connection: the request line, the headers, and a body sent
+// either with a Content-Length or in chunks. Anything we can't make sense of, or that's bigger
+// than `Limits` allows, becomes a `ParseError` that says which status to answer with.
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    // As it was sent: the path, and the query string if there is one
+    pub target: String,
+    pub version: Version,
+    // In the order they were sent, names as they were written
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Request {
+    // The first header called `name`, whatever case either is written in
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn path(&self) -> &str {
+        self.target.split_once('?').map_or(&self.target, |(path, _)| path)
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+}
+
+// How much we'll read before giving up on a request. Without these one client could have us
+// buffering forever.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    // The request line, and each header line
+    pub max_line: usize,
+    // All the header lines together, trailers included
+    pub max_header_bytes: usize,
+    pub max_headers: usize,
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { max_line: 8 * 1024, max_header_bytes: 32 * 1024, max_headers: 100, max_body: 1024 * 1024 }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    // The connection closed before the request started, which is a perfectly normal way to stop
+    Closed,
+    // 400: the request doesn't make sense, for the reason given
+    BadRequest(&'static str),
+    // 413: the body is more than `max_body`
+    PayloadTooLarge,
+    // 414: the request line is more than `max_line`, which in practice means the target is too long
+    UriTooLong,
+    // 431: too many headers, or too much of them
+    HeadersTooLarge,
+    // Reading from the connection failed
+    Io(io::Error),
+}
+
+impl ParseError {
+    // The status to answer with, or None when there's nobody to answer
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ParseError::Closed | ParseError::Io(_) => None,
+            ParseError::BadRequest(_) => Some(400),
+            ParseError::PayloadTooLarge => Some(413),
+            ParseError::UriTooLong => Some(414),
+            ParseError::HeadersTooLarge => Some(431),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Closed => write!(f, "connection closed"),
+            ParseError::BadRequest(reason) => write!(f, "bad request: {reason}"),
+            ParseError::PayloadTooLarge => write!(f, "request body too large"),
+            ParseError::UriTooLong => write!(f, "request line too long"),
+            ParseError::HeadersTooLarge => write!(f, "request headers too large"),
+            ParseError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+// What we send back. The body can be a file, which is copied out a piece at a time instead of
+// being read into memory first.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+}
+
+#[derive(Debug)]
+pub enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    // The file, and how much of it to send
+    File(File, u64),
+}
+
+impl Body {
+    pub fn len(&self) -> u64 {
+        match self {
+            Body::Empty => 0,
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File(_, len) => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response { status, headers: Vec::new(), body: Body::Empty }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, content_type: &str, body: impl Into<Vec<u8>>) -> Response {
+        self.body = Body::Bytes(body.into());
+        self.with_header("Content-Type", content_type)
+    }
+
+    // The first header called `name`, whatever case either is written in
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Everything but the body, for answering HEAD: the headers still say how long it would be
+    pub fn without_body(self) -> Response {
+        if self.header("Content-Length").is_some() {
+            return Response { body: Body::Empty, ..self };
+        }
+        let len = self.body.len().to_string();
+        Response { body: Body::Empty, ..self }.with_header("Content-Length", &len)
+    }
+
+    // Send the response. Content-Length comes from the body unless it's been set already, which
+    // is how an answer to HEAD says how long the body would have been. Nothing's flushed, so the
+    // answers to pipelined requests can go out together.
+    pub fn write_to<W: Write>(self, out: &mut W) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if self.header("Content-Length").is_none() {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+        out.write_all(head.as_bytes())?;
+
+        match self.body {
+            Body::Empty => {}
+            Body::Bytes(bytes) => out.write_all(&bytes)?,
+            Body::File(file, len) => {
+                let copied = io::copy(&mut file.take(len), out)?;
+                // The file shrank after we said how long it was; the client will notice the
+                // connection closing short, which is all we can do now
+                if copied < len {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while it was being sent"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The reason phrase that goes with a status code
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        301 => "Moved Permanently",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        _ => "Unknown",
+    }
+}
+
+// Read one request from `reader`
+pub fn read_request<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, ParseError> {
+    let line = match read_line(reader, limits.max_line)? {
+        Line::Complete(line) => line,
+        Line::End(partial) if partial.is_empty() => return Err(ParseError::Closed),
+        Line::End(_) => return Err(ParseError::BadRequest("request ended early")),
+        Line::TooLong => return Err(ParseError::UriTooLong),
+    };
+    let (method, target, version) = parse_request_line(&line)?;
+
+    // The trailers after a chunked body come out of the same `max_header_bytes`
+    let mut header_bytes = 0;
+    let headers = read_headers(reader, limits, &mut header_bytes)?;
+    let mut request = Request { method, target, version, headers, body: Vec::new() };
+
+    if version == Version::Http11 && request.header("Host").is_none() {
+        return Err(ParseError::BadRequest("missing Host header"));
+    }
+    request.body = read_body(reader, &request, limits, &mut header_bytes)?;
+    Ok(request)
+}
+
+fn parse_request_line(line: &str) -> Result<(String, String, Version), ParseError> {
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(ParseError::BadRequest("malformed request line"));
+    };
+
+    if method.is_empty() || !method.bytes().all(is_token) {
+        return Err(ParseError::BadRequest("malformed method"));
+    }
+    // Origin form (`/path?query`), or `*` for OPTIONS; absolute URLs are only for proxies
+    if !(target.starts_with('/') || target == "*") || target.bytes().any(|b| b.is_ascii_control()) {
+        return Err(ParseError::BadRequest("malformed request target"));
+    }
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        _ => return Err(ParseError::BadRequest("unsupported HTTP version")),
+    };
+
+    Ok((method.to_string(), target.to_string(), version))
+}
+
+// Header lines up to the blank line that ends them. `used` is how much of `max_header_bytes` has
+// gone already, since the trailers after a chunked body count too.
+fn read_headers<R: BufRead>(reader: &mut R, limits: &Limits, used: &mut usize) -> Result<Vec<(String, String)>, ParseError> {
+    let mut headers = Vec::new();
+    loop {
+        let line = match read_line(reader, limits.max_line)? {
+            Line::Complete(line) => line,
+            Line::End(_) => return Err(ParseError::BadRequest("request ended early")),
+            Line::TooLong => return Err(ParseError::HeadersTooLarge),
+        };
+        if line.is_empty() {
+            return Ok(headers);
+        }
+
+        *used += line.len();
+        if headers.len() == limits.max_headers || *used > limits.max_header_bytes {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        headers.push(parse_header(&line)?);
+    }
+}
+
+fn parse_header(line: &str) -> Result<(String, String), ParseError> {
+    // A line starting with whitespace continues the last one, which HTTP/1.1 no longer allows
+    if line.starts_with([' ', '\t']) {
+        return Err(ParseError::BadRequest("folded header line"));
+    }
+    let Some((name, value)) = line.split_once(':') else {
+        return Err(ParseError::BadRequest("header without a colon"));
+    };
+    // Including whitespace before the colon, which has been used to smuggle requests past proxies
+    if name.is_empty() || !name.bytes().all(is_token) {
+        return Err(ParseError::BadRequest("malformed header name"));
+    }
+    Ok((name.to_string(), value.trim_matches([' ', '\t']).to_string()))
+}
+
+fn read_body<R: BufRead>(reader: &mut R, request: &Request, limits: &Limits, header_bytes: &mut usize) -> Result<Vec<u8>, ParseError> {
+    let lengths: Vec<&str> = request.headers.iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        .map(|(_, value)| value.as_str())
+        .collect();
+
+    if let Some(encoding) = request.header("Transfer-Encoding") {
+        // Both at once is how requests get smuggled, so it's not allowed
+        if !lengths.is_empty() {
+            return Err(ParseError::BadRequest("both Content-Length and Transfer-Encoding"));
+        }
+        if !encoding.eq_ignore_ascii_case("chunked") {
+            return Err(ParseError::BadRequest("unsupported transfer encoding"));
+        }
+        return read_chunked(reader, limits, header_bytes);
+    }
+
+    let Some(&length) = lengths.first() else {
+        return Ok(Vec::new());
+    };
+    if lengths.iter().any(|other| *other != length) {
+        return Err(ParseError::BadRequest("conflicting Content-Length headers"));
+    }
+    let length = parse_length(length, 10).ok_or(ParseError::BadRequest("malformed Content-Length"))?;
+    if length > limits.max_body {
+        return Err(ParseError::PayloadTooLarge);
+    }
+
+    let mut body = vec![0; length];
+    read_exact(reader, &mut body)?;
+    Ok(body)
+}
+
+// A body sent as `SIZE\r\nDATA\r\n` chunks, ending with an empty one and maybe some trailers.
+// `header_bytes` is how much of `max_header_bytes` the headers have used already.
+fn read_chunked<R: BufRead>(reader: &mut R, limits: &Limits, header_bytes: &mut usize) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    loop {
+        let line = match read_line(reader, limits.max_line)? {
+            Line::Complete(line) => line,
+            Line::End(_) => return Err(ParseError::BadRequest("request ended early")),
+            Line::TooLong => return Err(ParseError::BadRequest("chunk size line too long")),
+        };
+        // Chunk extensions (`;name=value`) mean nothing to us
+        let size = line.split(';').next().unwrap_or_default().trim_end_matches([' ', '\t']);
+        let size = parse_length(size, 16).ok_or(ParseError::BadRequest("malformed chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        if size > limits.max_body - body.len() {
+            return Err(ParseError::PayloadTooLarge);
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        read_exact(reader, &mut body[start..])?;
+        match read_line(reader, 2)? {
+            Line::Complete(line) if line.is_empty() => {}
+            _ => return Err(ParseError::BadRequest("chunk longer than its size")),
+        }
+    }
+
+    // Trailers are headers that come after the body; we don't use them, but they have to be read
+    read_headers(reader, limits, header_bytes)?;
+    Ok(body)
+}
+
+// Digits only: no signs, no spaces, nothing that `parse` would let through but HTTP doesn't
+fn parse_length(digits: &str, radix: u32) -> Option<usize> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    usize::from_str_radix(digits, radix).ok()
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), ParseError> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => ParseError::BadRequest("body shorter than promised"),
+        _ => ParseError::Io(e),
+    })
+}
+
+// Characters allowed in methods and header names
+fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+enum Line {
+    // Without its line ending
+    Complete(String),
+    // The input ran out first; whatever there was of the line
+    End(Vec<u8>),
+    TooLong,
+}
+
+// One line, ended by CRLF (or a bare LF, which is common enough to accept), of at most `max`
+// bytes not counting the ending
+fn read_line<R: BufRead>(reader: &mut R, max: usize) -> Result<Line, ParseError> {
+    let mut line = Vec::new();
+    reader.take(max as u64 + 2).read_until(b'\n', &mut line)?;
+
+    if line.last() != Some(&b'\n') {
+        if line.len() as u64 == max as u64 + 2 {
+            return Ok(Line::TooLong);
+        }
+        return Ok(Line::End(line));
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    if line.len() > max {
+        return Ok(Line::TooLong);
+    }
+
+    String::from_utf8(line)
+        .map(Line::Complete)
+        .map_err(|_| ParseError::BadRequest("request isn't valid UTF-8"))
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(request: &str) -> Result<Request, ParseError> {
+        let limits = Limits { max_line: 64, max_header_bytes: 128, max_headers: 4, max_body: 16 };
+        read_request(&mut request.as_bytes(), &limits)
+    }
+
+    fn status(request: &str) -> Option<u16> {
+        parse(request).unwrap_err().status()
+    }
+
+    #[test]
+    fn reads_requests() {
+        let request = parse("GET /search?q=rust HTTP/1.1\r\nHost: example.com\r\nAccept:  text/html \r\n\r\n").unwrap();
+        assert_eq!("GET", request.method);
+        assert_eq!("/search", request.path());
+        assert_eq!(Some("q=rust"), request.query());
+        assert_eq!(Version::Http11, request.version);
+        assert_eq!(Some("text/html"), request.header("accept"));
+        assert!(request.body.is_empty());
+
+        // HTTP/1.0 doesn't need a Host, and bare newlines are fine
+        let request = parse("GET / HTTP/1.0\n\n").unwrap();
+        assert_eq!(Version::Http10, request.version);
+        assert_eq!(None, request.query());
+    }
+
+    #[test]
+    fn reads_bodies() {
+        let request = parse("POST /echo HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhelloGET").unwrap();
+        assert_eq!(b"hello", &request.body[..]);
+
+        let chunked = "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+            4;name=value\r\nWiki\r\nA\r\npedia in\r\n\r\n0\r\nExpires: never\r\n\r\n";
+        assert_eq!(b"Wikipedia in\r\n", &parse(chunked).unwrap().body[..]);
+    }
+
+    #[test]
+    fn leaves_the_next_request_alone() {
+        let mut input: &[u8] = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nhiGET /next HTTP/1.1\r\nHost: a\r\n\r\n";
+        let limits = Limits::default();
+        assert_eq!(b"hi", &read_request(&mut input, &limits).unwrap().body[..]);
+        assert_eq!("/next", read_request(&mut input, &limits).unwrap().target);
+        assert!(matches!(read_request(&mut input, &limits), Err(ParseError::Closed)));
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        assert_eq!(Some(400), status("GET /\r\n\r\n"));
+        assert_eq!(Some(400), status("GET  / HTTP/1.1\r\nHost: a\r\n\r\n"));
+        assert_eq!(Some(400), status("GET http://a/ HTTP/1.1\r\nHost: a\r\n\r\n"));
+        assert_eq!(Some(400), status("GET / HTTP/2.0\r\nHost: a\r\n\r\n"));
+        assert_eq!(Some(400), status("GET / HTTP/1.1\r\n\r\n"));
+        assert_eq!(Some(400), status("GET / HTTP/1.1\r\nHost : a\r\n\r\n"));
+        assert_eq!(Some(400), status("GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n"));
+        let not_utf8 = read_request(&mut &b"GET /\xff HTTP/1.1\r\nHost: a\r\n\r\n"[..], &Limits::default());
+        assert_eq!(Some(400), not_utf8.unwrap_err().status());
+        assert_eq!(Some(400), status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: -1\r\n\r\n"));
+        assert_eq!(Some(400), status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab"));
+        assert_eq!(Some(400), status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n"));
+        assert_eq!(Some(400), status("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip\r\n\r\n"));
+        assert_eq!(Some(400), status("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"));
+        assert_eq!(Some(400), status("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nabc\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn rejects_truncated_requests() {
+        assert!(matches!(parse(""), Err(ParseError::Closed)));
+        assert_eq!(Some(400), status("GET / HT"));
+        assert_eq!(Some(400), status("GET / HTTP/1.1\r\nHost: a\r\n"));
+        assert_eq!(Some(400), status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhi"));
+        assert_eq!(Some(400), status("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhi"));
+    }
+
+    #[test]
+    fn writes_responses() {
+        let mut out = Vec::new();
+        Response::new(200).with_body("text/plain", "hi").write_to(&mut out).unwrap();
+        assert_eq!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nhi", String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        Response::new(404).with_header("Content-Length", "10").write_to(&mut out).unwrap();
+        assert_eq!("HTTP/1.1 404 Not Found\r\nContent-Length: 10\r\n\r\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let long = "a".repeat(100);
+        assert_eq!(Some(414), status(&format!("GET /{long} HTTP/1.1\r\nHost: a\r\n\r\n")));
+        assert_eq!(Some(431), status(&format!("GET / HTTP/1.1\r\nHost: a\r\nX: {long}\r\n\r\n")));
+        assert_eq!(Some(431), status("GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n\r\n"));
+        let big = format!("X: {}\r\n", "b".repeat(50));
+        assert_eq!(Some(431), status(&format!("GET / HTTP/1.1\r\nHost: a\r\n{big}{big}{big}\r\n")));
+        // Trailers share the headers' allowance, so what fits in either alone doesn't fit in both
+        let chunked = "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n";
+        assert!(parse(&format!("{chunked}{big}\r\n0\r\n\r\n")).is_ok());
+        assert!(parse(&format!("{chunked}\r\n0\r\n{big}\r\n")).is_ok());
+        assert_eq!(Some(431), status(&format!("{chunked}{big}\r\n0\r\n{big}\r\n")));
+        assert_eq!(Some(413), status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 17\r\n\r\n"));
+        assert_eq!(Some(413), status("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n10\r\n0123456789abcdef\r\n1\r\n"));
+    }
+}
@@ -0,0 +1,191 @@
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+pub mod connection;
+mod error;
+pub mod files;
+pub mod http;
+pub mod router;
+mod shutdown;
+
+pub use error::ServerError;
+pub use shutdown::{install_signal_handlers, shutdown_requested};
+
+// A fixed set of worker threads that run jobs off a shared queue, so one slow client only ties up
+// one worker instead of the whole server. The queue is bounded: once it's full, `execute` waits
+// for a worker to free up, which stops us accepting connections faster than we can serve them.
+// Dropping the pool (or calling `shutdown`) lets the workers finish everything already queued.
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    // None once we've started shutting down, which is what tells the workers to stop
+    sender: Option<mpsc::SyncSender<Job>>,
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Debug, PartialEq)]
+pub enum PoolCreationError {
+    NoWorkers,
+    NoQueue,
+}
+
+impl fmt::Display for PoolCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolCreationError::NoWorkers => write!(f, "a thread pool needs at least one worker"),
+            PoolCreationError::NoQueue => write!(f, "a thread pool's queue needs room for at least one job"),
+        }
+    }
+}
+
+impl std::error::Error for PoolCreationError {}
+
+impl ThreadPool {
+    // `size` workers, with room for `queue_size` jobs waiting for one of them
+    pub fn build(size: usize, queue_size: usize) -> Result<ThreadPool, PoolCreationError> {
+        if size == 0 {
+            return Err(PoolCreationError::NoWorkers);
+        }
+        if queue_size == 0 {
+            return Err(PoolCreationError::NoQueue);
+        }
+
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size).map(|id| Worker::new(id, Arc::clone(&receiver))).collect();
+
+        Ok(ThreadPool { workers, sender: Some(sender) })
+    }
+
+    // Run `f` on the next free worker, waiting for room in the queue if it's full
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // The workers only stop once the sender's gone, so this can't fail while we have it
+        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+
+    // Stop taking jobs and wait for the workers to finish the ones already queued
+    pub fn shutdown(self) {
+        // Everything happens in drop; this is just a name for it
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // With the sender gone, each worker's `recv` fails once the queue is empty
+        drop(self.sender.take());
+
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+}
+
+struct Worker {
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+        let thread = thread::spawn(move || loop {
+            // The lock is only held while waiting for a job, never while running one, so a job
+            // that panics can't poison it
+            let message = receiver.lock().unwrap().recv();
+
+            let Ok(job) = message else {
+                break;
+            };
+
+            // A job that panics takes down the job, not the worker. The panic message has
+            // already been printed by the panic hook; we just note that we carried on.
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                eprintln!("Worker {id}: job panicked ({}); carrying on", panic_message(&*payload));
+            }
+        });
+
+        Worker { thread: Some(thread) }
+    }
+}
+
+// What a job panicked with, when it was a message
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "no message"
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+
+    #[test]
+    fn needs_workers_and_a_queue() {
+        assert_eq!(Some(PoolCreationError::NoWorkers), ThreadPool::build(0, 1).err());
+        assert_eq!(Some(PoolCreationError::NoQueue), ThreadPool::build(1, 0).err());
+    }
+
+    #[test]
+    fn runs_jobs_at_the_same_time() {
+        // Every job waits for all the others, so this only finishes if they all run at once
+        let pool = ThreadPool::build(4, 4).unwrap();
+        let barrier = Arc::new(Barrier::new(4));
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..4 {
+            let barrier = Arc::clone(&barrier);
+            let sender = sender.clone();
+            pool.execute(move || {
+                barrier.wait();
+                sender.send(()).unwrap();
+            });
+        }
+        assert_eq!(4, receiver.iter().take(4).count());
+    }
+
+    #[test]
+    fn survives_panicking_jobs() {
+        let pool = ThreadPool::build(1, 4).unwrap();
+        let done = Arc::new(AtomicUsize::new(0));
+        pool.execute(|| panic!("bad request"));
+        for _ in 0..3 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.shutdown();
+        assert_eq!(3, done.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn shutdown_finishes_queued_jobs() {
+        let pool = ThreadPool::build(2, 16).unwrap();
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..16 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(std::time::Duration::from_millis(5));
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.shutdown();
+        assert_eq!(16, done.load(Ordering::SeqCst));
+    }
+}
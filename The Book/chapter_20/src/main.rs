@@ -1,41 +1,103 @@
-use std::{fs, io::{prelude::*, BufReader}, net::{TcpListener, TcpStream}};
+use std::{env, fs, io::ErrorKind, net::TcpListener, path::Path, process, sync::Arc, thread, time::Duration};
 
+use chapter_20::connection::{self, Options};
+use chapter_20::files::StaticFiles;
+use chapter_20::http::{Request, Response};
+use chapter_20::router::{Handler, Params, Router};
+use chapter_20::{ServerError, ThreadPool};
+
+const ADDRESS: &str = "127.0.0.1:7878";
+
+// How long to wait between checks for a shutdown signal when nobody's connecting
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    
-    for stream in listener.incoming() {
-        let stream = stream.unwrap(); // uses shadowing to unwrap Result<>
-        
-        handle_connection(stream);
+    if let Err(e) = run() {
+        eprintln!("Problem starting server: {e}");
+        process::exit(1);
     }
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader
-        .lines()
-        .next()
-        .unwrap()
-        .unwrap();
-    
-    let (status_line, filename) = 
-        if request_line == "GET / HTTP/1.1" {
-            ("HTTP/1.1 200 OK", "hello.html")
-        } else {
-            ("HTTP/1.1 404 NOT FOUND", "404.html")
-        };
+fn run() -> Result<(), ServerError> {
+    // WORKERS and QUEUE_SIZE size the thread pool; by default it's a worker per core
+    let workers = env_number("WORKERS")?.unwrap_or_else(|| thread::available_parallelism().map_or(4, |n| n.get()));
+    let queue_size = env_number("QUEUE_SIZE")?.unwrap_or(workers * 16);
+    let pool = ThreadPool::build(workers, queue_size)?;
+
+    // DOC_ROOT is the directory we serve files from; DIR_LISTINGS=1 lists directories without an index
+    let root = env::var("DOC_ROOT").unwrap_or_else(|_| "public".to_string());
+    // Read once, now, so it doesn't matter where we were started from; without one a 404 is just a 404
+    let not_found = fs::read_to_string(Path::new(&root).join("404.html")).ok();
+    let files = StaticFiles::new(root).with_listings(env::var("DIR_LISTINGS").is_ok_and(|value| value == "1"));
 
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
-    
-    let response = format!(
-        "{status_line}\r\n\
-            Content-Length: {length}\r\n\r\n\
-            {contents}"
+    let router = Arc::new(
+        Router::new()
+            .get("/health", |_: &Request, _: &Params| Ok(Response::new(200).with_body("text/plain; charset=utf-8", "ok\n")))
+            .fallback(move |request: &Request, params: &Params| {
+                let response = files.handle(request, params)?;
+                match &not_found {
+                    Some(page) if response.status == 404 && request.method != "HEAD" => {
+                        Ok(response.with_body("text/html; charset=utf-8", page.clone()))
+                    }
+                    _ => Ok(response),
+                }
+            }),
     );
-    
-    stream.write_all(response.as_bytes()).unwrap();
-    
-    
-}
\ No newline at end of file
+
+    // IDLE_TIMEOUT and REQUEST_TIMEOUT (in seconds) say how long a connection can wait for a
+    // request and how long sending one can take; MAX_REQUESTS is how many it's kept open for
+    let defaults = Options::default();
+    let seconds = |name, default| -> Result<Duration, ServerError> {
+        Ok(env_number(name)?.map_or(default, |secs| Duration::from_secs(secs as u64)))
+    };
+    let options = Options {
+        idle_timeout: seconds("IDLE_TIMEOUT", defaults.idle_timeout)?,
+        request_timeout: seconds("REQUEST_TIMEOUT", defaults.request_timeout)?,
+        max_requests: env_number("MAX_REQUESTS")?.unwrap_or(defaults.max_requests),
+        ..defaults
+    };
+
+    let listener = TcpListener::bind(ADDRESS).map_err(|e| ServerError::Bind(ADDRESS.to_string(), e))?;
+    chapter_20::install_signal_handlers();
+
+    // Accepting without blocking is what lets us notice a shutdown signal between connections
+    listener.set_nonblocking(true)?;
+
+    while !chapter_20::shutdown_requested() {
+        let (stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Failed to accept a connection: {e}");
+                continue;
+            }
+        };
+
+        // A connection that goes wrong is that connection's problem; the server carries on
+        if let Err(e) = stream.set_nonblocking(false) {
+            eprintln!("{peer}: dropping connection: {e}");
+            continue;
+        }
+        let router = Arc::clone(&router);
+        pool.execute(move || {
+            if let Err(e) = connection::serve(stream, &*router, &options) {
+                eprintln!("{peer}: dropping connection: {e}");
+            }
+        });
+    }
+
+    println!("Shutting down; finishing the requests in progress.");
+    pool.shutdown();
+    Ok(())
+}
+
+// An environment variable that should hold a number, if it's set
+fn env_number(name: &str) -> Result<Option<usize>, ServerError> {
+    let Ok(value) = env::var(name) else {
+        return Ok(None);
+    };
+    value.parse().map(Some).map_err(|_| ServerError::NotANumber(name.to_string(), value))
+}
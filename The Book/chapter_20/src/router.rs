@@ -0,0 +1,289 @@
+use crate::files::{self, StaticFiles};
+use crate::http::{Request, Response};
+use crate::ServerError;
+
+// Something that answers requests. Closures taking the request and the route's parameters are
+// handlers, so a route can be as small as `|_, _| Ok(Response::new(204))`. A handler that fails
+// gets the client a 500, and the error is logged.
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &Request, params: &Params) -> Result<Response, ServerError>;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request, &Params) -> Result<Response, ServerError> + Send + Sync,
+{
+    fn handle(&self, request: &Request, params: &Params) -> Result<Response, ServerError> {
+        self(request, params)
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &Request, _params: &Params) -> Result<Response, ServerError> {
+        Ok(self.serve(request))
+    }
+}
+
+// What the `:name` and `*name` parts of a route matched, percent-decoded
+#[derive(Debug, Default, PartialEq)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter().find(|(param, _)| param == name).map(|(_, value)| value.as_str())
+    }
+}
+
+// Sends each request to the first route whose method and pattern match it. Patterns are paths
+// where a `:name` segment matches any one segment and a final `*name` matches the rest of the
+// path (possibly nothing):
+//
+//   Router::new()
+//       .get("/users/:id", show_user)
+//       .post("/users", create_user)
+//       .get("/assets/*file", assets)
+//       .fallback(StaticFiles::new("public"))
+//
+// A GET route answers HEAD too. A path that matches a route for some other method gets 405 with
+// the methods that would have worked; anything else goes to the fallback, which answers 404
+// unless it's been given something better to do.
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Box<dyn Handler>,
+}
+
+struct Route {
+    method: String,
+    pattern: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router { routes: Vec::new(), fallback: Box::new(|_: &Request, _: &Params| Ok(Response::new(404))) }
+    }
+
+    // Panics if `pattern` isn't a path, or has a wildcard anywhere but at the end: that's a
+    // mistake in the program, not something a request could cause
+    pub fn route(mut self, method: &str, pattern: &str, handler: impl Handler + 'static) -> Router {
+        self.routes.push(Route { method: method.to_string(), pattern: parse_pattern(pattern), handler: Box::new(handler) });
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: impl Handler + 'static) -> Router {
+        self.route("GET", pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: impl Handler + 'static) -> Router {
+        self.route("POST", pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: impl Handler + 'static) -> Router {
+        self.route("PUT", pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: impl Handler + 'static) -> Router {
+        self.route("DELETE", pattern, handler)
+    }
+
+    // What answers requests that no route matches
+    pub fn fallback(mut self, handler: impl Handler + 'static) -> Router {
+        self.fallback = Box::new(handler);
+        self
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, request: &Request, _params: &Params) -> Result<Response, ServerError> {
+        let head = request.method == "HEAD";
+        let mut allowed: Vec<&str> = Vec::new();
+
+        for route in &self.routes {
+            let Some(params) = match_path(&route.pattern, request.path()) else {
+                continue;
+            };
+            if route.method == request.method {
+                return route.handler.handle(request, &params);
+            }
+            if head && route.method == "GET" {
+                return route.handler.handle(request, &params).map(Response::without_body);
+            }
+            if !allowed.contains(&route.method.as_str()) {
+                allowed.push(&route.method);
+            }
+        }
+
+        if allowed.is_empty() {
+            return self.fallback.handle(request, &Params::default());
+        }
+        if allowed.contains(&"GET") {
+            allowed.push("HEAD");
+        }
+        Ok(Response::new(405).with_header("Allow", &allowed.join(", ")))
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    let Some(rest) = pattern.strip_prefix('/') else {
+        panic!("route pattern {pattern:?} should start with /");
+    };
+    let segments: Vec<Segment> = rest.split('/')
+        .map(|segment| {
+            if let Some(name) = segment.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = segment.strip_prefix('*') {
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Literal(segment.to_string())
+            }
+        })
+        .collect();
+
+    let wildcards = segments.iter().filter(|segment| matches!(segment, Segment::Wildcard(_))).count();
+    assert!(
+        wildcards == 0 || (wildcards == 1 && matches!(segments.last(), Some(Segment::Wildcard(_)))),
+        "route pattern {pattern:?} can only have a wildcard at the end"
+    );
+    segments
+}
+
+// The parameters if `path` fits `pattern`
+fn match_path(pattern: &[Segment], path: &str) -> Option<Params> {
+    let mut params = Vec::new();
+    let mut parts = path.strip_prefix('/')?.split('/');
+
+    for segment in pattern {
+        match segment {
+            Segment::Wildcard(name) => {
+                let rest: Vec<&str> = parts.by_ref().collect();
+                params.push((name.clone(), files::percent_decode(&rest.join("/"))?));
+            }
+            Segment::Param(name) => {
+                let part = parts.next().filter(|part| !part.is_empty())?;
+                params.push((name.clone(), files::percent_decode(part)?));
+            }
+            Segment::Literal(literal) => {
+                if parts.next()? != literal {
+                    return None;
+                }
+            }
+        }
+    }
+
+    // Everything in the path has to be used up
+    match parts.next() {
+        Some(_) => None,
+        None => Some(Params(params)),
+    }
+}
+
+
+/* ---------------------------------------------------------------------------------------------- */
+/*                                           Testing                                              */
+/* ---------------------------------------------------------------------------------------------- */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Body, Version};
+
+    fn request(method: &str, target: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: Version::Http11,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn text(response: &Response) -> &str {
+        match &response.body {
+            Body::Bytes(bytes) => std::str::from_utf8(bytes).unwrap(),
+            _ => "",
+        }
+    }
+
+    fn echo(name: &'static str) -> impl Handler {
+        move |_: &Request, params: &Params| {
+            let mut body = name.to_string();
+            for (param, value) in &params.0 {
+                body.push_str(&format!(" {param}={value}"));
+            }
+            Ok(Response::new(200).with_body("text/plain", body))
+        }
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", echo("home"))
+            .get("/users/new", echo("new user"))
+            .get("/users/:id", echo("user"))
+            .delete("/users/:id", echo("delete user"))
+            .get("/users/:id/posts/:post", echo("post"))
+            .post("/users", echo("create user"))
+            .get("/files/*path", echo("file"))
+    }
+
+    #[test]
+    fn routes_requests() {
+        let router = router();
+        let handle = |method, target| router.handle(&request(method, target), &Params::default()).unwrap();
+
+        assert_eq!("home", text(&handle("GET", "/")));
+        assert_eq!("user id=42", text(&handle("GET", "/users/42?tab=posts")));
+        assert_eq!("user id=ada lovelace", text(&handle("GET", "/users/ada%20lovelace")));
+        assert_eq!("delete user id=42", text(&handle("DELETE", "/users/42")));
+        assert_eq!("post id=1 post=2", text(&handle("GET", "/users/1/posts/2")));
+        assert_eq!("create user", text(&handle("POST", "/users")));
+        // Earlier routes win
+        assert_eq!("new user", text(&handle("GET", "/users/new")));
+
+        assert_eq!("file path=css/site.css", text(&handle("GET", "/files/css/site.css")));
+        assert_eq!("file path=", text(&handle("GET", "/files/")));
+
+        assert_eq!(404, handle("GET", "/users/").status);
+        assert_eq!(404, handle("GET", "/users/1/posts").status);
+        assert_eq!(404, handle("GET", "/nowhere").status);
+    }
+
+    #[test]
+    fn other_methods() {
+        let router = router();
+        let handle = |method, target| router.handle(&request(method, target), &Params::default()).unwrap();
+
+        let response = handle("HEAD", "/users/7");
+        assert_eq!(200, response.status);
+        assert_eq!(Some("9"), response.header("Content-Length"));
+        assert!(response.body.is_empty());
+
+        let response = handle("PUT", "/users/7");
+        assert_eq!(405, response.status);
+        assert_eq!(Some("GET, DELETE, HEAD"), response.header("Allow"));
+        assert_eq!(Some("POST"), handle("GET", "/users").header("Allow"));
+    }
+
+    #[test]
+    fn falls_back() {
+        let router = router().fallback(|request: &Request, _: &Params| Ok(Response::new(200).with_body("text/plain", request.path())));
+        assert_eq!("/elsewhere", text(&router.handle(&request("GET", "/elsewhere"), &Params::default()).unwrap()));
+    }
+
+    #[test]
+    #[should_panic(expected = "wildcard at the end")]
+    fn wildcards_go_last() {
+        Router::new().get("/*path/edit", echo("edit"));
+    }
+}
@@ -0,0 +1,41 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set by SIGINT (Ctrl-C) or SIGTERM. The accept loop checks it, stops taking new connections and
+// lets the thread pool finish the ones it has.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+// Storing to an atomic is about all a signal handler can safely do
+#[cfg(unix)]
+extern "C" fn on_signal(_signal: i32) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+// Without a crate there's no safe wrapper for this, so we go straight to the C library
+#[cfg(unix)]
+unsafe extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+#[cfg(unix)]
+const SIGINT: i32 = 2;
+#[cfg(unix)]
+const SIGTERM: i32 = 15;
+
+// Have SIGINT and SIGTERM ask for a graceful shutdown instead of killing the process outright
+#[cfg(unix)]
+pub fn install_signal_handlers() {
+    for signum in [SIGINT, SIGTERM] {
+        // SAFETY: `on_signal` only touches an atomic, which is async-signal-safe
+        unsafe {
+            signal(signum, on_signal);
+        }
+    }
+}
+
+// Elsewhere Ctrl-C just stops the process, the way it always has
+#[cfg(not(unix))]
+pub fn install_signal_handlers() {}